@@ -1,62 +1,373 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use clap::Parser;
 use rpassword::prompt_password;
 use tokio::runtime::Runtime;
 
 use gophkeeper_client::cli::CliApp;
-use gophkeeper_client::cli::app::{Commands, SecretTypeCommands};
+use gophkeeper_client::cli::app::{
+  AccountCommands, Commands, GetCommands, KeyfileCommands, RecoveryCommands, SecretTypeCommands,
+  ShareCommands, VaultCommands,
+};
 
 use gophkeeper_client::cli::app::Cli;
-use gophkeeper_client::core::models::SecretPayload;
+use gophkeeper_client::core::crypto::{KdfParams, Padding, Share};
+use gophkeeper_client::core::hardening;
+use gophkeeper_client::core::keyfile::Keyfile;
+use gophkeeper_client::core::manifest::IntegrityIssue;
+use gophkeeper_client::core::models::{SecretPayload, ShareBundle};
+use gophkeeper_client::core::recovery::RecoveryKey;
+use gophkeeper_client::core::secret::{SecretString, Sensitive};
+use gophkeeper_client::core::sshkey;
+use gophkeeper_client::core::totp::{self, TotpSeed};
+#[cfg(unix)]
+use gophkeeper_client::core::{
+  GopherApp,
+  agent::{self, Agent, AgentOptions, Prompt},
+};
+use gophkeeper_client::core::{KeyfileRequired, SessionExpired, WrongPassword, format_remaining};
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::time::Duration;
 
 fn main() -> Result<()> {
   let cli = Cli::parse();
+  for warning in hardening::harden().warnings() {
+    eprintln!("Warning: {}", warning);
+  }
   let rt = Runtime::new()?;
 
-  rt.block_on(run(cli))
+  rt.block_on(run(cli)).map_err(|e| {
+    if e.is::<KeyfileRequired>() {
+      anyhow!("{}; pass it with --keyfile", e)
+    } else {
+      e
+    }
+  })
 }
 
 async fn run(cli: Cli) -> Result<()> {
   let mut app = CliApp::new(cli.address.clone());
+  app.inner.set_kdf_params(KdfParams {
+    m_cost: cli.kdf_memory,
+    t_cost: cli.kdf_iterations,
+    p_cost: cli.kdf_parallelism,
+  });
+  app.inner.set_cipher(cli.cipher);
+  if let Some(buckets) = cli.pad_buckets {
+    app.inner.set_padding(Padding::new(buckets)?);
+  }
+  if let Some(path) = &cli.keyfile {
+    app.inner.set_keyfile(Some(Keyfile::load(path)?));
+  }
+
+  match app.inner.try_auto_login().await {
+    Ok(true) => println!("Auto login successful."),
+    Ok(false) => {}
+    Err(e) if e.is::<SessionExpired>() => {
+      eprintln!("{}", expired_hint(&app));
+    }
+    Err(e) => eprintln!("Stored session not used: {}", e),
+  }
+
+  let result = execute(&mut app, cli.command, &cli.address).await;
+  if let Err(e) = &result
+    && e.is::<SessionExpired>()
+  {
+    app.inner.expire_session().await?;
+    return Err(anyhow!("{}", expired_hint(&app)));
+  }
+  result
+}
 
-  if app.inner.try_auto_login().await.is_ok() {
-    println!("Auto login successful.");
+fn expired_hint(app: &CliApp) -> String {
+  match app.inner.get_current_user() {
+    Some(login) => format!(
+      "Session expired, log in again with `gk-cli login --login {}`",
+      login
+    ),
+    None => "Session expired, please log in again".to_string(),
   }
+}
 
-  match cli.command {
+async fn execute(app: &mut CliApp, command: Commands, address: &str) -> Result<()> {
+  match command {
     Commands::Register { login, pass } => {
-      app.register(login, pass).await?;
+      app.register(login, pass.into()).await?;
       println!("Registered successfully.");
     }
 
     Commands::Login { login, pass } => {
-      app.login(login, pass).await?;
+      app.login(login, pass.into()).await?;
       println!("Login successful.");
     }
 
+    Commands::Account { action } => match action {
+      AccountCommands::Migrate { login, pass } => {
+        app.migrate_account(login, pass.into()).await?;
+        println!("Account migrated. Use the same password to log in from now on.");
+      }
+      AccountCommands::ChangePassword => {
+        let old = SecretString::new(prompt_password("Current master password: ")?);
+        let new = read_new_password()?;
+        app.change_master_password(old, new).await?;
+        println!("Master password changed.");
+      }
+      AccountCommands::RecoveryKey => {
+        ensure_master_password(app).await?;
+        let recovery = app.create_recovery_key().await?;
+        print_emergency_kit(
+          app.inner.get_current_user().unwrap_or_default(),
+          &recovery.words(),
+        );
+      }
+      AccountCommands::Recover { login } => {
+        let words = SecretString::new(prompt_password("Recovery key (24 words): ")?);
+        let recovery = RecoveryKey::parse(&words)?;
+        let new = read_new_password()?;
+        app.recover(login, recovery, new).await?;
+        println!("Vault recovered. Log in with the new master password from now on.");
+      }
+    },
+
+    Commands::Vault { action } => match action {
+      VaultCommands::Convert { cipher } => {
+        let pass = SecretString::new(prompt_password("Master password: ")?);
+        let dropped_recovery = app.convert_cipher(pass, cipher).await?;
+        println!("Vault converted to {}.", cipher.id());
+        if dropped_recovery {
+          println!(
+            "The old recovery key no longer works. Run `account recovery-key` for a new one."
+          );
+        }
+      }
+      VaultCommands::Accept => {
+        ensure_master_password(app).await?;
+        app.accept_vault_state().await?;
+        println!("Current server state accepted as the vault state.");
+      }
+    },
+
+    Commands::Recovery { action } => match action {
+      RecoveryCommands::Split { shares, threshold } => {
+        ensure_master_password(app).await?;
+        // Validate before the current recovery key gets replaced.
+        RecoveryKey::generate().split(shares, threshold)?;
+
+        let recovery = app.create_recovery_key().await?;
+        let login = app.inner.get_current_user().unwrap_or_default();
+        for share in recovery.split(shares, threshold)? {
+          println!(
+            "GophKeeper recovery share {} of {} for {} ({} needed)",
+            share.index, shares, login, threshold
+          );
+          print_words(&share.to_words());
+          println!();
+        }
+        println!(
+          "Hand each share to a different trustee. The previous recovery key no longer works."
+        );
+      }
+      RecoveryCommands::Combine { login } => {
+        let mut shares: Vec<Share> = Vec::new();
+        loop {
+          let words = SecretString::new(prompt_password(format!("Share {}: ", shares.len() + 1))?);
+          match Share::from_words(&words) {
+            Ok(share) if shares.iter().any(|s| s.index == share.index) => {
+              eprintln!("Share {} was already entered", share.index);
+              continue;
+            }
+            Ok(share) => shares.push(share),
+            Err(e) => {
+              eprintln!("{}", e);
+              continue;
+            }
+          }
+          if shares.len() >= usize::from(shares[0].threshold) {
+            break;
+          }
+        }
+
+        let recovery = RecoveryKey::combine(&shares)?;
+        let new = read_new_password()?;
+        app.recover(login, recovery, new).await?;
+        println!("Vault recovered. Log in with the new master password from now on.");
+      }
+    },
+
+    Commands::Keyfile { action } => match action {
+      KeyfileCommands::Generate { path } => {
+        Keyfile::generate(&path)?;
+        println!("Keyfile written to {}.", path.display());
+        println!("Keep a backup: once the vault requires it, it cannot be opened without it.");
+        println!("Run `keyfile set {}` to require it.", path.display());
+      }
+      KeyfileCommands::Set { path } => {
+        let keyfile = Keyfile::load(&path)?;
+        let pass = SecretString::new(prompt_password("Master password: ")?);
+        app.change_keyfile(pass, Some(keyfile)).await?;
+        println!(
+          "The vault now requires {}. Pass it with --keyfile from now on.",
+          path.display()
+        );
+      }
+      KeyfileCommands::Remove => {
+        let pass = SecretString::new(prompt_password("Master password: ")?);
+        app.change_keyfile(pass, None).await?;
+        println!("The vault no longer requires a keyfile.");
+      }
+    },
+
+    Commands::Share { action } => match action {
+      ShareCommands::Key => {
+        ensure_master_password(app).await?;
+        println!("{}", app.public_key().await?);
+      }
+      ShareCommands::Export { id, to, out } => {
+        ensure_master_password(app).await?;
+        let share = app.export_share(&id, &to).await?;
+        let path = out.unwrap_or_else(|| format!("{}.gkshare", id).into());
+        std::fs::write(&path, serde_json::to_string_pretty(&share)?)?;
+        println!("Share bundle written to {}.", path.display());
+      }
+      ShareCommands::Import { path } => {
+        let share: ShareBundle = serde_json::from_str(&std::fs::read_to_string(&path)?)
+          .map_err(|e| anyhow!("Invalid share bundle: {}", e))?;
+        ensure_master_password(app).await?;
+        app.import_share(&share).await?;
+        println!("Shared secret added to the vault.");
+      }
+    },
+
     Commands::Sync => {
-      ensure_master_password(&mut app)?;
-      let secrets = app.sync().await?;
-      println!("{:#?}", secrets);
+      ensure_master_password(app).await?;
+      let report = app.sync().await?;
+      for secret in &report.secrets {
+        // Custom fields go on lines of their own, with their kind.
+        let payload = secret.payload.expose();
+        let fixed = Sensitive::new(payload.clone().with_fields(Vec::new()));
+        println!(
+          "{} ({}): {:#?}",
+          secret.id,
+          secret.secret_type,
+          fixed.expose()
+        );
+        for field in payload.custom_fields() {
+          println!("  {} ({}): {}", field.name, field.kind, field.value);
+        }
+      }
+      if !report.upgraded.is_empty() {
+        println!(
+          "Upgraded {} entries from the legacy format.",
+          report.upgraded.len()
+        );
+      }
+      for issue in &report.issues {
+        eprintln!("Warning: {}", issue);
+      }
+      if report.issues.iter().any(|issue| {
+        !matches!(
+          issue,
+          IntegrityIssue::Tampered(_) | IntegrityIssue::Unreadable { .. }
+        )
+      }) {
+        eprintln!(
+          "The server may have dropped or rolled back entries. Run `vault accept` if this change is expected."
+        );
+      }
     }
 
     Commands::Logout => {
       app.logout().await?;
     }
 
+    Commands::Totp { id } => {
+      ensure_master_password(app).await?;
+      let report = app.sync().await?;
+      let secret = report
+        .secrets
+        .iter()
+        .find(|s| s.id == id)
+        .ok_or_else(|| anyhow!("Secret {} not found", id))?;
+      let current = totp::current_code(secret.payload.expose())?;
+      println!("{}", current.code.expose());
+      eprintln!("Valid for {}s", current.remaining);
+    }
+
+    #[cfg(unix)]
+    Commands::SshAgent {
+      socket,
+      timeout,
+      confirm,
+    } => {
+      ensure_master_password(app).await?;
+      let socket = match socket {
+        Some(path) => path,
+        None => agent::default_socket()?,
+      };
+      let options = AgentOptions {
+        confirm,
+        idle_timeout: (timeout > 0).then(|| Duration::from_secs(timeout * 60)),
+      };
+      // The agent keeps the unlocked vault for as long as it runs.
+      let vault = std::mem::replace(&mut app.inner, GopherApp::new(address.to_string()));
+      let agent = Agent::start(vault, socket, options, Arc::new(TerminalPrompt::default())).await?;
+      println!(
+        "SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;",
+        agent.socket().display()
+      );
+      eprintln!("Press Ctrl+C to stop the agent.");
+      agent.run().await?;
+    }
+
+    Commands::Generate(args) => {
+      let generated = args.generate()?;
+      println!("{}", generated.value.expose());
+      eprintln!("Entropy: {:.1} bits", generated.entropy);
+    }
+
+    Commands::Status => {
+      println!("Server: {}", address);
+      match app.inner.get_current_user() {
+        Some(login) => println!("User: {}", login),
+        None => println!("User: not logged in"),
+      }
+      match app.inner.session_remaining() {
+        Some(remaining) if remaining.is_zero() => println!("Session: expired"),
+        Some(remaining) => println!("Session: expires in {}", format_remaining(remaining)),
+        None if app.inner.get_current_user().is_some() => println!("Session: expired"),
+        None => println!("Session: none"),
+      }
+    }
+
     Commands::Add { secret_type } => {
-      ensure_master_password(&mut app)?;
+      ensure_master_password(app).await?;
 
       let payload = match secret_type {
-        SecretTypeCommands::Password(args) => SecretPayload::Password {
-          title: args.title,
-          login: args.login,
-          password: args.password,
-          url: args.url,
-        },
+        SecretTypeCommands::Password(args) => {
+          let password = match args.password {
+            Some(password) => password,
+            None => {
+              let generated = args.generator.generate()?;
+              println!(
+                "Generated a password with {:.0} bits of entropy.",
+                generated.entropy
+              );
+              generated.value.expose().clone()
+            }
+          };
+          SecretPayload::Password {
+            title: args.title,
+            login: args.login,
+            password,
+            url: args.url,
+            fields: args.custom.fields,
+          }
+        }
         SecretTypeCommands::Note(args) => SecretPayload::Note {
           title: args.title,
           content: args.content,
+          fields: args.custom.fields,
         },
         SecretTypeCommands::Card(args) => SecretPayload::Card {
           title: args.title,
@@ -64,23 +375,135 @@ async fn run(cli: Cli) -> Result<()> {
           number: args.number,
           expiry: args.expiry,
           cvv: args.cvv,
+          fields: args.custom.fields,
         },
+        SecretTypeCommands::Totp(args) => {
+          let mut seed = TotpSeed::parse(&args.seed)?;
+          seed.algorithm = args.algorithm.unwrap_or(seed.algorithm);
+          seed.digits = args.digits.unwrap_or(seed.digits);
+          seed.period = args.period.unwrap_or(seed.period);
+          totp::check_params(seed.digits, seed.period)?;
+          seed.to_payload(args.title).with_fields(args.custom.fields)
+        }
+        SecretTypeCommands::SshKey(args) => {
+          let payload = match args.key {
+            Some(path) => {
+              let private_key = SecretString::new(std::fs::read_to_string(&path)?);
+              sshkey::import(args.title, &private_key, args.passphrase, args.comment)?
+            }
+            None => {
+              let payload = sshkey::generate(args.title, args.passphrase, args.comment)?;
+              if let SecretPayload::SshKey { public_key, .. } = &payload {
+                println!("{}", public_key);
+              }
+              payload
+            }
+          };
+          payload.with_fields(args.custom.fields)
+        }
+        SecretTypeCommands::File(args) => {
+          app
+            .add_file(&args.path, args.title, args.custom.fields)
+            .await?;
+          println!("File added successfully");
+          return Ok(());
+        }
       };
       app.add_secret(payload).await?;
       println!("Secret added successfully");
     }
+    Commands::Get { secret_type } => match secret_type {
+      GetCommands::File { id, out } => {
+        ensure_master_password(app).await?;
+        let size = app.save_file(&id, &out).await?;
+        println!("Wrote {} bytes to {}", size, out.display());
+      }
+    },
     Commands::Delete { id } => {
-      app.delete_secret(id).await?;
+      ensure_master_password(app).await?;
+      let orphaned = app.delete_secret(id).await?;
       println!("Secret deleted.");
+      if orphaned {
+        eprintln!(
+          "Warning: the file entry could not be decrypted, so its chunks were left on the server."
+        );
+      }
     }
   }
   Ok(())
 }
 
-fn ensure_master_password(app: &mut CliApp) -> Result<()> {
-  if app.inner.master_password_is_none() {
-    let pass = prompt_password("Enter master password: ")?;
-    app.inner.set_master_password(pass);
+// Confirmations come from the terminal the agent was started in, one at a time.
+#[cfg(unix)]
+#[derive(Default)]
+struct TerminalPrompt {
+  asking: std::sync::Mutex<()>,
+}
+
+#[cfg(unix)]
+impl Prompt for TerminalPrompt {
+  fn confirm(&self, request: &str) -> bool {
+    let _asking = self.asking.lock();
+    eprint!("{} [y/N] ", request);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+  }
+
+  fn notice(&self, message: &str) {
+    eprintln!("{}", message);
+  }
+}
+
+fn read_new_password() -> Result<SecretString> {
+  let new = SecretString::new(prompt_password("New master password: ")?);
+  if new != SecretString::new(prompt_password("Confirm new master password: ")?) {
+    return Err(anyhow!("Passwords do not match"));
+  }
+  Ok(new)
+}
+
+fn print_emergency_kit(login: &str, words: &SecretString) {
+  println!("GophKeeper emergency kit for {}", login);
+  println!();
+  print_words(words);
+  println!();
+  println!("Print this page or write the words down and keep them offline.");
+  println!("Anyone holding them can open the vault. Recover with `account recover`.");
+}
+
+fn print_words(words: &SecretString) {
+  for (row, chunk) in words
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .chunks(4)
+    .enumerate()
+  {
+    let line: Vec<String> = chunk
+      .iter()
+      .enumerate()
+      .map(|(col, word)| format!("{:>2}. {:<10}", row * 4 + col + 1, word))
+      .collect();
+    println!("  {}", line.join(" ").trim_end());
+  }
+}
+
+const UNLOCK_ATTEMPTS: usize = 3;
+
+async fn ensure_master_password(app: &mut CliApp) -> Result<()> {
+  let mut attempt = 1;
+  while !app.inner.is_unlocked() {
+    let pass = SecretString::new(prompt_password("Enter master password: ")?);
+    match app.unlock(pass).await {
+      Err(e) if e.is::<WrongPassword>() && attempt < UNLOCK_ATTEMPTS => {
+        if app.inner.requires_keyfile() {
+          eprintln!("Wrong master password or keyfile, try again.");
+        } else {
+          eprintln!("Wrong master password, try again.");
+        }
+        attempt += 1;
+      }
+      result => result?,
+    }
   }
   Ok(())
 }
@@ -1,6 +1,11 @@
+use gophkeeper_client::core::hardening;
 use gophkeeper_client::tui::{TuiApp, run_tui};
 
 fn main() -> std::io::Result<()> {
-  let app = TuiApp::new()?;
+  let report = hardening::harden();
+  let mut app = TuiApp::new()?;
+  if !report.failed.is_empty() {
+    app.notify_error(format!("Warning: {}", report.warnings().join("; ")));
+  }
   run_tui(app)
 }
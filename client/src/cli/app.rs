@@ -1,8 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 
 use crate::core::GopherApp;
-use crate::core::models::{DecryptedSecret, SecretPayload};
+use crate::core::crypto::Cipher;
+use crate::core::generator::{self, Generated, PassphrasePolicy, PasswordPolicy};
+use crate::core::keyfile::Keyfile;
+use crate::core::models::{CustomField, FieldKind, SecretPayload, ShareBundle, SyncReport};
+use crate::core::recovery::RecoveryKey;
+use crate::core::secret::SecretString;
+use crate::core::totp::TotpAlgorithm;
+use url::Url;
 
 pub struct CliApp {
   pub inner: GopherApp,
@@ -15,22 +23,89 @@ impl CliApp {
     }
   }
 
-  pub async fn register(&self, login: String, pass: String) -> Result<()> {
+  pub async fn register(&self, login: String, pass: SecretString) -> Result<()> {
     self.inner.register(login, pass).await
   }
 
-  pub async fn login(&mut self, login: String, pass: String) -> Result<()> {
-    self.inner.login(login.clone(), pass.clone()).await?;
-    self.inner.set_current_user(login);
-    self.inner.set_master_password(pass);
-    Ok(())
+  pub async fn login(&mut self, login: String, pass: SecretString) -> Result<()> {
+    self.inner.login(login, pass).await
+  }
+
+  pub async fn migrate_account(&mut self, login: String, pass: SecretString) -> Result<()> {
+    self.inner.migrate_account(login, pass).await
+  }
+
+  pub async fn change_master_password(
+    &mut self,
+    old: SecretString,
+    new: SecretString,
+  ) -> Result<()> {
+    self.inner.change_master_password(old, new).await
+  }
+
+  pub async fn convert_cipher(&mut self, pass: SecretString, cipher: Cipher) -> Result<bool> {
+    self.inner.convert_cipher(pass, cipher).await
+  }
+
+  pub async fn change_keyfile(
+    &mut self,
+    pass: SecretString,
+    keyfile: Option<Keyfile>,
+  ) -> Result<()> {
+    self.inner.change_keyfile(pass, keyfile).await
+  }
+
+  pub async fn accept_vault_state(&self) -> Result<()> {
+    self.inner.accept_vault_state().await
+  }
+
+  pub async fn create_recovery_key(&self) -> Result<RecoveryKey> {
+    self.inner.create_recovery_key().await
+  }
+
+  pub async fn recover(
+    &mut self,
+    login: String,
+    recovery: RecoveryKey,
+    new: SecretString,
+  ) -> Result<()> {
+    self.inner.recover(login, recovery, new).await
+  }
+
+  pub async fn public_key(&self) -> Result<String> {
+    self.inner.public_key().await
+  }
+
+  pub async fn export_share(&self, id: &str, recipient: &str) -> Result<ShareBundle> {
+    self.inner.export_share(id, recipient).await
+  }
+
+  pub async fn import_share(&self, share: &ShareBundle) -> Result<()> {
+    self.inner.import_share(share).await
+  }
+
+  pub async fn unlock(&mut self, pass: SecretString) -> Result<()> {
+    self.inner.unlock(pass).await
   }
 
   pub async fn add_secret(&self, payload: SecretPayload) -> Result<()> {
     self.inner.add_secret(payload).await
   }
 
-  pub async fn sync(&self) -> Result<Vec<DecryptedSecret>> {
+  pub async fn add_file(
+    &self,
+    path: &Path,
+    title: Option<String>,
+    fields: Vec<CustomField>,
+  ) -> Result<()> {
+    self.inner.add_file(path, title, fields).await
+  }
+
+  pub async fn save_file(&self, id: &str, out: &Path) -> Result<u64> {
+    self.inner.save_file(id, out).await
+  }
+
+  pub async fn sync(&self) -> Result<SyncReport> {
     self.inner.sync_and_decrypt().await
   }
 
@@ -38,7 +113,7 @@ impl CliApp {
     self.inner.logout().await
   }
 
-  pub async fn delete_secret(&self, id: String) -> Result<()> {
+  pub async fn delete_secret(&self, id: String) -> Result<bool> {
     self.inner.delete_secret(id).await
   }
 }
@@ -49,6 +124,44 @@ pub struct Cli {
   #[arg(short, long, default_value = "http://localhost:8080")]
   pub address: String,
 
+  #[arg(
+    long,
+    default_value_t = 19456,
+    help = "Argon2id memory cost in KiB for new vaults"
+  )]
+  pub kdf_memory: u32,
+
+  #[arg(long, default_value_t = 2, help = "Argon2id iterations for new vaults")]
+  pub kdf_iterations: u32,
+
+  #[arg(
+    long,
+    default_value_t = 1,
+    help = "Argon2id parallelism for new vaults"
+  )]
+  pub kdf_parallelism: u32,
+
+  #[arg(
+    long,
+    default_value = "aes-256-gcm",
+    value_parser = parse_cipher,
+    help = "Cipher for new vaults: aes-256-gcm or xchacha20-poly1305"
+  )]
+  pub cipher: Cipher,
+
+  #[arg(
+    long,
+    value_delimiter = ',',
+    help = "Comma-separated plaintext size buckets in bytes that entries are padded to"
+  )]
+  pub pad_buckets: Option<Vec<usize>>,
+
+  #[arg(
+    long,
+    help = "Keyfile to unlock the vault with, next to the master password"
+  )]
+  pub keyfile: Option<PathBuf>,
+
   #[command(subcommand)]
   pub command: Commands,
 }
@@ -71,12 +184,206 @@ pub enum Commands {
     #[command(subcommand)]
     secret_type: SecretTypeCommands,
   },
+  Get {
+    #[command(subcommand)]
+    secret_type: GetCommands,
+  },
+  Account {
+    #[command(subcommand)]
+    action: AccountCommands,
+  },
+  Vault {
+    #[command(subcommand)]
+    action: VaultCommands,
+  },
+  Recovery {
+    #[command(subcommand)]
+    action: RecoveryCommands,
+  },
+  Keyfile {
+    #[command(subcommand)]
+    action: KeyfileCommands,
+  },
+  Share {
+    #[command(subcommand)]
+    action: ShareCommands,
+  },
   Sync,
   Delete {
     #[arg(short, long)]
     id: String,
   },
   Logout,
+  #[command(about = "Show the current user and how long the session lasts")]
+  Status,
+  #[command(about = "Generate a random password or passphrase")]
+  Generate(GeneratorArgs),
+  #[command(about = "Show the current code of a TOTP entry")]
+  Totp {
+    id: String,
+  },
+  #[cfg(unix)]
+  #[command(about = "Serve the SSH keys in the vault as an ssh-agent")]
+  SshAgent {
+    #[arg(
+      long,
+      help = "Socket path, $XDG_RUNTIME_DIR/gophkeeper/agent.sock by default"
+    )]
+    socket: Option<PathBuf>,
+    #[arg(
+      long,
+      default_value_t = 60,
+      help = "Lock after this many minutes without a signature, 0 for never"
+    )]
+    timeout: u64,
+    #[arg(
+      long,
+      help = "Ask before every signature with this key entry; repeatable"
+    )]
+    confirm: Vec<String>,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum AccountCommands {
+  #[command(about = "Move an account created by an older client to derived login credentials")]
+  Migrate {
+    #[arg(short, long)]
+    login: String,
+    #[arg(short, long)]
+    pass: String,
+  },
+  #[command(about = "Change the master password")]
+  ChangePassword,
+  #[command(about = "Generate a recovery key that can replace a forgotten master password")]
+  RecoveryKey,
+  #[command(about = "Set a new master password using the recovery key")]
+  Recover {
+    #[arg(short, long)]
+    login: String,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum RecoveryCommands {
+  #[command(
+    about = "Replace the recovery key with shares for trustees, any K of which recover the vault"
+  )]
+  Split {
+    #[arg(short = 'n', long)]
+    shares: u8,
+    #[arg(short = 'k', long)]
+    threshold: u8,
+  },
+  #[command(about = "Rebuild the recovery key from trustee shares and set a new master password")]
+  Combine {
+    #[arg(short, long)]
+    login: String,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum KeyfileCommands {
+  #[command(about = "Write a new random keyfile")]
+  Generate { path: PathBuf },
+  #[command(about = "Require a keyfile next to the master password to unlock the vault")]
+  Set { path: PathBuf },
+  #[command(about = "Stop requiring a keyfile to unlock the vault")]
+  Remove,
+}
+
+#[derive(Subcommand)]
+pub enum ShareCommands {
+  #[command(about = "Print the public key others seal share bundles to")]
+  Key,
+  #[command(about = "Write a secret to a file only the holder of a public key can open")]
+  Export {
+    #[arg(short, long)]
+    id: String,
+    #[arg(short, long, help = "Recipient public key from `share key`")]
+    to: String,
+    #[arg(short, long, help = "Bundle file to write, <id>.gkshare by default")]
+    out: Option<PathBuf>,
+  },
+  #[command(about = "Add the secret from a share bundle to the vault")]
+  Import { path: PathBuf },
+}
+
+#[derive(Subcommand)]
+pub enum VaultCommands {
+  #[command(about = "Re-encrypt every entry with another cipher")]
+  Convert {
+    #[arg(short, long, value_parser = parse_cipher)]
+    cipher: Cipher,
+  },
+  #[command(about = "Accept entries flagged as missing or rolled back by sync")]
+  Accept,
+}
+
+fn parse_cipher(id: &str) -> Result<Cipher> {
+  Cipher::from_id(id)
+}
+
+#[derive(clap::Args)]
+pub struct FieldArgs {
+  #[arg(
+    long = "field",
+    value_name = "NAME=VALUE",
+    value_parser = parse_field,
+    help = "Custom field, repeatable; write NAME:hidden, NAME:url or NAME:date to set its kind"
+  )]
+  pub fields: Vec<CustomField>,
+}
+
+// NAME=VALUE, with the kind optionally after the name: "PIN:hidden=1234".
+fn parse_field(arg: &str) -> Result<CustomField> {
+  let (name, value) = arg
+    .split_once('=')
+    .context("Custom fields are written as NAME=VALUE")?;
+  let (name, kind) = match name.rsplit_once(':') {
+    Some((name, "hidden")) => (name, FieldKind::Hidden),
+    Some((name, "visible")) => (name, FieldKind::Visible),
+    Some((name, "url")) => (name, FieldKind::Url),
+    Some((name, "date")) => (name, FieldKind::Date),
+    _ => (name, FieldKind::Visible),
+  };
+  if name.trim().is_empty() {
+    bail!("Custom field has no name");
+  }
+
+  match kind {
+    FieldKind::Url => {
+      Url::parse(value).with_context(|| format!("Field {} is not a valid URL", name))?;
+    }
+    FieldKind::Date if !is_date(value) => {
+      bail!("Field {} is not a date in YYYY-MM-DD form", name)
+    }
+    _ => {}
+  }
+
+  Ok(CustomField {
+    name: name.trim().into(),
+    value: value.into(),
+    kind,
+  })
+}
+
+fn is_date(value: &str) -> bool {
+  let number = |part: &str, len: usize| {
+    if part.len() == len && part.bytes().all(|b| b.is_ascii_digit()) {
+      part.parse::<u32>().ok()
+    } else {
+      None
+    }
+  };
+  match value.split('-').collect::<Vec<_>>()[..] {
+    [year, month, day] => {
+      number(year, 4).is_some()
+        && number(month, 2).is_some_and(|m| (1..=12).contains(&m))
+        && number(day, 2).is_some_and(|d| (1..=31).contains(&d))
+    }
+    _ => false,
+  }
 }
 
 #[derive(Subcommand)]
@@ -84,6 +391,23 @@ pub enum SecretTypeCommands {
   Password(PasswordArgs),
   Note(NoteArgs),
   Card(CardArgs),
+  #[command(about = "A TOTP seed, from an otpauth:// URI or a base32 secret")]
+  Totp(TotpArgs),
+  #[command(about = "An OpenSSH private key, imported or generated as ed25519")]
+  SshKey(SshKeyArgs),
+  #[command(about = "A file, encrypted and uploaded in chunks")]
+  File(FileArgs),
+}
+
+#[derive(Subcommand)]
+pub enum GetCommands {
+  #[command(about = "Decrypt a file entry to disk")]
+  File {
+    #[arg(short, long)]
+    id: String,
+    #[arg(short, long, help = "Where to write the file; it must not exist yet")]
+    out: PathBuf,
+  },
 }
 
 #[derive(clap::Args)]
@@ -92,10 +416,72 @@ pub struct PasswordArgs {
   pub title: String,
   #[arg(short, long)]
   pub login: String,
-  #[arg(short, long)]
-  pub password: String,
+  #[arg(short, long, required_unless_present = "generate")]
+  pub password: Option<String>,
   #[arg(short, long)]
   pub url: Option<String>,
+  #[arg(
+    long,
+    conflicts_with = "password",
+    help = "Generate the password instead of passing it"
+  )]
+  pub generate: bool,
+  #[command(flatten)]
+  pub generator: GeneratorArgs,
+  #[command(flatten)]
+  pub custom: FieldArgs,
+}
+
+#[derive(clap::Args)]
+pub struct GeneratorArgs {
+  #[arg(long, default_value_t = 20, help = "Password length")]
+  pub length: usize,
+  #[arg(long, help = "Leave out lowercase letters")]
+  pub no_lowercase: bool,
+  #[arg(long, help = "Leave out uppercase letters")]
+  pub no_uppercase: bool,
+  #[arg(long, help = "Leave out digits")]
+  pub no_digits: bool,
+  #[arg(long, help = "Leave out symbols")]
+  pub no_symbols: bool,
+  #[arg(
+    long,
+    help = "Leave out characters that are easy to misread, like l, 1, O and 0"
+  )]
+  pub exclude_ambiguous: bool,
+  #[arg(
+    long,
+    default_value_t = 1,
+    help = "Minimum number of characters from each class"
+  )]
+  pub min_per_class: usize,
+  #[arg(long, help = "Make a passphrase of random words instead")]
+  pub passphrase: bool,
+  #[arg(long, default_value_t = 6, help = "Number of words in a passphrase")]
+  pub words: usize,
+  #[arg(long, default_value = "-", help = "Separator between passphrase words")]
+  pub separator: String,
+}
+
+impl GeneratorArgs {
+  pub fn generate(&self) -> Result<Generated> {
+    if self.passphrase {
+      generator::passphrase(&PassphrasePolicy {
+        words: self.words,
+        separator: self.separator.clone(),
+      })
+    } else {
+      generator::password(&PasswordPolicy {
+        length: self.length,
+        lowercase: !self.no_lowercase,
+        uppercase: !self.no_uppercase,
+        digits: !self.no_digits,
+        symbols: !self.no_symbols,
+        exclude_ambiguous: self.exclude_ambiguous,
+        min_per_class: self.min_per_class,
+      })
+    }
+  }
 }
 
 #[derive(clap::Args)]
@@ -104,6 +490,8 @@ pub struct NoteArgs {
   pub title: String,
   #[arg(short, long)]
   pub content: String,
+  #[command(flatten)]
+  pub custom: FieldArgs,
 }
 
 #[derive(clap::Args)]
@@ -118,4 +506,141 @@ pub struct CardArgs {
   pub expiry: String,
   #[arg(short, long)]
   pub cvv: String,
+  #[command(flatten)]
+  pub custom: FieldArgs,
+}
+
+#[derive(clap::Args)]
+pub struct TotpArgs {
+  #[arg(short, long)]
+  pub title: String,
+  #[arg(short, long, help = "otpauth://totp/ URI or base32 secret")]
+  pub seed: String,
+  #[arg(long, help = "SHA1, SHA256 or SHA512, overriding the URI")]
+  pub algorithm: Option<TotpAlgorithm>,
+  #[arg(long, help = "Code length, overriding the URI")]
+  pub digits: Option<u32>,
+  #[arg(long, help = "Seconds each code is valid, overriding the URI")]
+  pub period: Option<u64>,
+  #[command(flatten)]
+  pub custom: FieldArgs,
+}
+
+#[derive(clap::Args)]
+pub struct SshKeyArgs {
+  #[arg(short, long)]
+  pub title: String,
+  #[arg(
+    short,
+    long,
+    required_unless_present = "generate",
+    help = "OpenSSH private key file"
+  )]
+  pub key: Option<PathBuf>,
+  #[arg(
+    long,
+    conflicts_with = "key",
+    help = "Generate a new ed25519 key instead"
+  )]
+  pub generate: bool,
+  #[arg(
+    short,
+    long,
+    help = "Passphrase of the key, or to protect the generated one"
+  )]
+  pub passphrase: Option<String>,
+  #[arg(
+    short,
+    long,
+    help = "Comment, taken from the key or the title by default"
+  )]
+  pub comment: Option<String>,
+  #[command(flatten)]
+  pub custom: FieldArgs,
+}
+
+#[derive(clap::Args)]
+pub struct FileArgs {
+  #[arg(short, long)]
+  pub path: PathBuf,
+  #[arg(short, long, help = "Title, the file name by default")]
+  pub title: Option<String>,
+  #[command(flatten)]
+  pub custom: FieldArgs,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fields_carry_their_kind() {
+    let field = parse_field("PIN:hidden=12=34").unwrap();
+    assert_eq!(field.name, "PIN");
+    assert_eq!(field.value, "12=34");
+    assert_eq!(field.kind, FieldKind::Hidden);
+
+    let field = parse_field(" Answer =blue").unwrap();
+    assert_eq!(field.name, "Answer");
+    assert_eq!(field.kind, FieldKind::Visible);
+
+    // Only a known kind is split off the name.
+    let field = parse_field("ratio:3=1").unwrap();
+    assert_eq!(field.name, "ratio:3");
+    assert_eq!(field.kind, FieldKind::Visible);
+
+    assert_eq!(
+      parse_field("Site:url=https://example.com").unwrap().kind,
+      FieldKind::Url
+    );
+    assert_eq!(
+      parse_field("Renew:date=2027-02-28").unwrap().kind,
+      FieldKind::Date
+    );
+
+    // Kinds print the way they are written.
+    for (kind, value) in [
+      (FieldKind::Hidden, "1234"),
+      (FieldKind::Visible, "blue"),
+      (FieldKind::Url, "https://example.com"),
+      (FieldKind::Date, "2027-02-28"),
+    ] {
+      let arg = format!("Field:{}={}", kind, value);
+      assert_eq!(parse_field(&arg).unwrap().kind, kind);
+    }
+  }
+
+  #[test]
+  fn malformed_fields_are_refused() {
+    for arg in [
+      "no value",
+      "=value",
+      " :hidden=value",
+      "Site:url=not a url",
+      "Renew:date=28.02.2027",
+    ] {
+      assert!(parse_field(arg).is_err(), "{:?} was accepted", arg);
+    }
+  }
+
+  #[test]
+  fn dates_are_year_month_day() {
+    for value in ["2027-02-28", "1999-12-31", "2000-01-01"] {
+      assert!(is_date(value), "{:?} was refused", value);
+    }
+    for value in [
+      "",
+      "2027-2-28",
+      "27-02-28",
+      "2027-13-01",
+      "2027-00-10",
+      "2027-01-32",
+      "2027-01-00",
+      "2027-01-01-01",
+      "2027/01/01",
+      "+027-01-01",
+    ] {
+      assert!(!is_date(value), "{:?} was accepted", value);
+    }
+  }
 }
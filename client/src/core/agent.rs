@@ -0,0 +1,655 @@
+use anyhow::{Context, Result, anyhow, bail};
+use rsa::BigUint;
+use rsa::pkcs1v15;
+use sha2::{Sha256, Sha512};
+use signature::{SignatureEncoding, Signer};
+use ssh_key::private::RsaKeypair;
+use ssh_key::{Algorithm, HashAlg, Mpint, PrivateKey, Signature};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+
+use crate::core::app::GopherApp;
+use crate::core::models::SecretPayload;
+use crate::core::secret::SecretString;
+
+// Message numbers from the ssh-agent protocol (draft-miller-ssh-agent).
+const FAILURE: u8 = 5;
+const SUCCESS: u8 = 6;
+const REQUEST_IDENTITIES: u8 = 11;
+const IDENTITIES_ANSWER: u8 = 12;
+const SIGN_REQUEST: u8 = 13;
+const SIGN_RESPONSE: u8 = 14;
+const LOCK: u8 = 22;
+const UNLOCK: u8 = 23;
+
+const RSA_SHA2_256: u32 = 2;
+const RSA_SHA2_512: u32 = 4;
+
+// The limit OpenSSH's own agent uses; no real request comes close.
+const MAX_MESSAGE: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct AgentOptions {
+  // Entry ids whose keys sign only after the user agrees, every time.
+  pub confirm: Vec<String>,
+  // Keys are dropped after this long without a signature.
+  pub idle_timeout: Option<Duration>,
+}
+
+// How the agent reaches whoever started it.
+pub trait Prompt: Send + Sync {
+  fn confirm(&self, request: &str) -> bool;
+  fn notice(&self, message: &str);
+}
+
+struct AgentKey {
+  id: String,
+  key: PrivateKey,
+  blob: Vec<u8>,
+  comment: String,
+  fingerprint: String,
+}
+
+struct State {
+  app: GopherApp,
+  keys: Vec<AgentKey>,
+  last_used: Instant,
+}
+
+impl State {
+  fn is_locked(&self) -> bool {
+    !self.app.is_unlocked()
+  }
+
+  fn lock(&mut self) {
+    self.keys.clear();
+    self.app.lock();
+  }
+}
+
+// $XDG_RUNTIME_DIR/gophkeeper/agent.sock, or next to the session tokens when
+// there is no runtime directory.
+pub fn default_socket() -> Result<PathBuf> {
+  let dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+    Some(dir) => PathBuf::from(dir),
+    None => home::home_dir()
+      .context("Missing HOME directory")?
+      .join(".local/state"),
+  };
+  Ok(dir.join("gophkeeper/agent.sock"))
+}
+
+// Serves the SSH keys of an unlocked vault. Locking, by request or after the
+// idle timeout, forgets both the keys and the vault key; the master password,
+// sent as the unlock passphrase, brings them back.
+pub struct Agent {
+  listener: UnixListener,
+  socket: PathBuf,
+  state: Arc<Mutex<State>>,
+  options: Arc<AgentOptions>,
+  prompt: Arc<dyn Prompt>,
+}
+
+impl Agent {
+  pub async fn start(
+    app: GopherApp,
+    socket: PathBuf,
+    options: AgentOptions,
+    prompt: Arc<dyn Prompt>,
+  ) -> Result<Self> {
+    let keys = load_keys(&app).await?;
+    if let Some(id) = options
+      .confirm
+      .iter()
+      .find(|id| !keys.iter().any(|key| key.id == **id))
+    {
+      bail!("No SSH key entry {}", id);
+    }
+    prompt.notice(&format!("Serving {} SSH keys from the vault", keys.len()));
+
+    Ok(Self {
+      listener: bind(&socket).await?,
+      socket,
+      state: Arc::new(Mutex::new(State {
+        app,
+        keys,
+        last_used: Instant::now(),
+      })),
+      options: Arc::new(options),
+      prompt,
+    })
+  }
+
+  pub fn socket(&self) -> &Path {
+    &self.socket
+  }
+
+  // Answers requests until interrupted, then removes the socket.
+  pub async fn run(self) -> Result<()> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+      tokio::select! {
+        accepted = self.listener.accept() => {
+          let (stream, _) = accepted?;
+          tokio::spawn(handle(
+            stream,
+            self.state.clone(),
+            self.options.clone(),
+            self.prompt.clone(),
+          ));
+        }
+        _ = ticker.tick() => {
+          let Some(timeout) = self.options.idle_timeout else { continue };
+          let mut state = self.state.lock().await;
+          if !state.is_locked() && state.last_used.elapsed() >= timeout {
+            state.lock();
+            self.prompt.notice("Locked after the idle timeout; unlock with `ssh-add -X`");
+          }
+        }
+        _ = tokio::signal::ctrl_c() => break,
+      }
+    }
+
+    let _ = fs::remove_file(&self.socket).await;
+    Ok(())
+  }
+}
+
+async fn load_keys(app: &GopherApp) -> Result<Vec<AgentKey>> {
+  let report = app.sync_and_decrypt().await?;
+  let mut keys = Vec::new();
+  for secret in &report.secrets {
+    let SecretPayload::SshKey {
+      title,
+      private_key,
+      passphrase,
+      comment,
+      ..
+    } = secret.payload.expose()
+    else {
+      continue;
+    };
+
+    let mut key = PrivateKey::from_openssh(private_key)
+      .map_err(|e| anyhow!("SSH key {} is damaged: {}", title, e))?;
+    if key.is_encrypted() {
+      let passphrase = passphrase.as_deref().unwrap_or_default();
+      key = key
+        .decrypt(passphrase)
+        .map_err(|_| anyhow!("Stored passphrase does not open SSH key {}", title))?;
+    }
+    let public_key = key.public_key();
+    keys.push(AgentKey {
+      id: secret.id.clone(),
+      blob: public_key
+        .to_bytes()
+        .map_err(|e| anyhow!("SSH key {} is damaged: {}", title, e))?,
+      comment: comment.clone(),
+      fingerprint: public_key.fingerprint(HashAlg::Sha256).to_string(),
+      key,
+    });
+  }
+  Ok(keys)
+}
+
+async fn bind(socket: &Path) -> Result<UnixListener> {
+  let dir = socket.parent().context("Invalid socket path")?;
+  let mut builder = fs::DirBuilder::new();
+  builder.recursive(true).mode(0o700);
+  builder.create(dir).await?;
+
+  if fs::try_exists(socket).await? {
+    if UnixStream::connect(socket).await.is_ok() {
+      bail!("An agent is already listening on {}", socket.display());
+    }
+    fs::remove_file(socket).await?;
+  }
+
+  let listener = UnixListener::bind(socket)
+    .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+  // The directory already keeps other users out; this is for a socket path
+  // given by hand.
+  {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600)).await?;
+  }
+  Ok(listener)
+}
+
+async fn handle(
+  mut stream: UnixStream,
+  state: Arc<Mutex<State>>,
+  options: Arc<AgentOptions>,
+  prompt: Arc<dyn Prompt>,
+) {
+  while let Ok(request) = read_message(&mut stream).await {
+    let reply = respond(&request, &state, &options, &prompt).await;
+    if write_message(&mut stream, &reply).await.is_err() {
+      break;
+    }
+  }
+}
+
+async fn read_message(stream: &mut UnixStream) -> Result<Vec<u8>> {
+  let len = stream.read_u32().await? as usize;
+  if len == 0 || len > MAX_MESSAGE {
+    bail!("Invalid agent message length {}", len);
+  }
+  let mut message = vec![0u8; len];
+  stream.read_exact(&mut message).await?;
+  Ok(message)
+}
+
+async fn write_message(stream: &mut UnixStream, message: &[u8]) -> Result<()> {
+  stream.write_u32(message.len() as u32).await?;
+  stream.write_all(message).await?;
+  Ok(())
+}
+
+async fn respond(
+  request: &[u8],
+  state: &Mutex<State>,
+  options: &AgentOptions,
+  prompt: &Arc<dyn Prompt>,
+) -> Vec<u8> {
+  let (kind, body) = match request.split_first() {
+    Some((kind, body)) => (*kind, body),
+    None => return vec![FAILURE],
+  };
+  let result = match kind {
+    REQUEST_IDENTITIES => Ok(identities(&*state.lock().await)),
+    SIGN_REQUEST => sign(body, state, options, prompt).await,
+    LOCK => {
+      state.lock().await.lock();
+      prompt.notice("Locked on request");
+      Ok(vec![SUCCESS])
+    }
+    UNLOCK => unlock(body, state, prompt).await,
+    _ => Err(anyhow!("Unsupported agent request {}", kind)),
+  };
+  result.unwrap_or_else(|_| vec![FAILURE])
+}
+
+fn identities(state: &State) -> Vec<u8> {
+  let mut reply = vec![IDENTITIES_ANSWER];
+  put_u32(&mut reply, state.keys.len() as u32);
+  for key in &state.keys {
+    put_string(&mut reply, &key.blob);
+    put_string(&mut reply, key.comment.as_bytes());
+  }
+  reply
+}
+
+async fn sign(
+  body: &[u8],
+  state: &Mutex<State>,
+  options: &AgentOptions,
+  prompt: &Arc<dyn Prompt>,
+) -> Result<Vec<u8>> {
+  let mut reader = Reader(body);
+  let blob = reader.string()?;
+  let data = reader.string()?;
+  let flags = reader.u32()?;
+
+  let request = {
+    let state = state.lock().await;
+    let key = find_key(&state, blob)?;
+    options
+      .confirm
+      .contains(&key.id)
+      .then(|| format!("Sign with {} ({})?", key.comment, key.fingerprint))
+  };
+  // The state stays unlocked while the user decides, so other connections
+  // and the idle timer carry on.
+  if let Some(request) = request {
+    let prompt = prompt.clone();
+    let allowed = tokio::task::spawn_blocking(move || prompt.confirm(&request)).await?;
+    if !allowed {
+      bail!("Signature refused");
+    }
+  }
+
+  let mut state = state.lock().await;
+  let signature = sign_with(&find_key(&state, blob)?.key, data, flags)?;
+  state.last_used = Instant::now();
+
+  let mut encoded = Vec::new();
+  put_string(&mut encoded, signature.algorithm().as_str().as_bytes());
+  put_string(&mut encoded, signature.as_bytes());
+  let mut reply = vec![SIGN_RESPONSE];
+  put_string(&mut reply, &encoded);
+  Ok(reply)
+}
+
+fn find_key<'a>(state: &'a State, blob: &[u8]) -> Result<&'a AgentKey> {
+  state
+    .keys
+    .iter()
+    .find(|key| key.blob == blob)
+    .context("Unknown key")
+}
+
+fn sign_with(key: &PrivateKey, data: &[u8], flags: u32) -> Result<Signature> {
+  match key.key_data().rsa() {
+    Some(keypair) => sign_rsa(keypair, data, flags),
+    None => key
+      .try_sign(data)
+      .map_err(|e| anyhow!("Signing failed: {}", e)),
+  }
+}
+
+// Not `rsa::RsaPrivateKey::try_from(keypair)`: in ssh-key 0.6.7 that passes
+// `p` twice instead of `p` and `q` (src/private/rsa.rs), so the key is rebuilt
+// here. Clients pick the hash through the flags; SHA-1 "ssh-rsa" is refused.
+fn sign_rsa(keypair: &RsaKeypair, data: &[u8], flags: u32) -> Result<Signature> {
+  let int = |value: &Mpint| BigUint::from_bytes_be(value.as_positive_bytes().unwrap_or_default());
+  let key = rsa::RsaPrivateKey::from_components(
+    int(&keypair.public.n),
+    int(&keypair.public.e),
+    int(&keypair.private.d),
+    vec![int(&keypair.private.p), int(&keypair.private.q)],
+  )?;
+
+  let (hash, signature) = if flags & RSA_SHA2_512 != 0 {
+    let signature = pkcs1v15::SigningKey::<Sha512>::new(key).try_sign(data)?;
+    (HashAlg::Sha512, signature.to_vec())
+  } else if flags & RSA_SHA2_256 != 0 {
+    let signature = pkcs1v15::SigningKey::<Sha256>::new(key).try_sign(data)?;
+    (HashAlg::Sha256, signature.to_vec())
+  } else {
+    bail!("SHA-1 ssh-rsa signatures are not supported");
+  };
+  Signature::new(Algorithm::Rsa { hash: Some(hash) }, signature)
+    .map_err(|e| anyhow!("Signing failed: {}", e))
+}
+
+async fn unlock(body: &[u8], state: &Mutex<State>, prompt: &Arc<dyn Prompt>) -> Result<Vec<u8>> {
+  let password = SecretString::new(
+    String::from_utf8(Reader(body).string()?.to_vec()).context("Invalid passphrase")?,
+  );
+  let mut state = state.lock().await;
+  if !state.is_locked() {
+    bail!("Agent is not locked");
+  }
+
+  let result = match state.app.unlock(password).await {
+    Ok(()) => load_keys(&state.app).await,
+    Err(e) => Err(e),
+  };
+  match result {
+    Ok(keys) => {
+      state.keys = keys;
+      state.last_used = Instant::now();
+      prompt.notice("Unlocked");
+      Ok(vec![SUCCESS])
+    }
+    Err(e) => {
+      state.lock();
+      prompt.notice(&format!("Unlock failed: {}", e));
+      Err(e)
+    }
+  }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    if self.0.len() < len {
+      bail!("Truncated agent message");
+    }
+    let (head, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(head)
+  }
+
+  fn u32(&mut self) -> Result<u32> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  fn string(&mut self) -> Result<&'a [u8]> {
+    let len = self.u32()? as usize;
+    self.take(len)
+  }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+  out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_string(out: &mut Vec<u8>, bytes: &[u8]) {
+  put_u32(out, bytes.len() as u32);
+  out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use signature::Verifier;
+
+  struct Answer(bool);
+
+  impl Prompt for Answer {
+    fn confirm(&self, _request: &str) -> bool {
+      self.0
+    }
+
+    fn notice(&self, _message: &str) {}
+  }
+
+  fn agent_key(id: &str, key: PrivateKey) -> AgentKey {
+    let public_key = key.public_key();
+    AgentKey {
+      id: id.into(),
+      blob: public_key.to_bytes().unwrap(),
+      comment: key.comment().into(),
+      fingerprint: public_key.fingerprint(HashAlg::Sha256).to_string(),
+      key,
+    }
+  }
+
+  fn ed25519() -> PrivateKey {
+    let mut key = PrivateKey::random(&mut aes_gcm::aead::OsRng, Algorithm::Ed25519).unwrap();
+    key.set_comment("ed@test");
+    key
+  }
+
+  fn rsa() -> PrivateKey {
+    PrivateKey::from_openssh(include_str!("../../testdata/agent_rsa_key")).unwrap()
+  }
+
+  // An unlocked state holding `keys`; the vault itself is never reached.
+  fn state(keys: Vec<AgentKey>) -> Mutex<State> {
+    Mutex::new(State {
+      app: GopherApp::new("http://127.0.0.1:9".into()),
+      keys,
+      last_used: Instant::now(),
+    })
+  }
+
+  fn sign_request(blob: &[u8], data: &[u8], flags: u32) -> Vec<u8> {
+    let mut request = vec![SIGN_REQUEST];
+    put_string(&mut request, blob);
+    put_string(&mut request, data);
+    put_u32(&mut request, flags);
+    request
+  }
+
+  // Returns the algorithm name and signature blob of a sign response.
+  fn read_signature(reply: &[u8]) -> (String, Vec<u8>) {
+    assert_eq!(reply[0], SIGN_RESPONSE);
+    let mut reader = Reader(&reply[1..]);
+    let mut encoded = Reader(reader.string().unwrap());
+    let algorithm = String::from_utf8(encoded.string().unwrap().to_vec()).unwrap();
+    (algorithm, encoded.string().unwrap().to_vec())
+  }
+
+  async fn ask(
+    state: &Mutex<State>,
+    options: &AgentOptions,
+    answer: bool,
+    request: &[u8],
+  ) -> Vec<u8> {
+    let prompt: Arc<dyn Prompt> = Arc::new(Answer(answer));
+    respond(request, state, options, &prompt).await
+  }
+
+  #[test]
+  fn reader_reads_what_was_put() {
+    let mut message = Vec::new();
+    put_u32(&mut message, 7);
+    put_string(&mut message, b"abc");
+    put_string(&mut message, b"");
+
+    let mut reader = Reader(&message);
+    assert_eq!(reader.u32().unwrap(), 7);
+    assert_eq!(reader.string().unwrap(), b"abc");
+    assert_eq!(reader.string().unwrap(), b"");
+    assert!(reader.u32().is_err());
+
+    // A length running past the end of the message.
+    assert!(Reader(&[0, 0, 0, 9, b'x']).string().is_err());
+  }
+
+  #[tokio::test]
+  async fn lists_identities() {
+    let ed = agent_key("a", ed25519());
+    let rsa = agent_key("b", rsa());
+    let blobs = [ed.blob.clone(), rsa.blob.clone()];
+    let state = state(vec![ed, rsa]);
+
+    let reply = ask(
+      &state,
+      &AgentOptions::default(),
+      true,
+      &[REQUEST_IDENTITIES],
+    )
+    .await;
+    assert_eq!(reply[0], IDENTITIES_ANSWER);
+    let mut reader = Reader(&reply[1..]);
+    assert_eq!(reader.u32().unwrap(), 2);
+    assert_eq!(reader.string().unwrap(), blobs[0]);
+    assert_eq!(reader.string().unwrap(), b"ed@test");
+    assert_eq!(reader.string().unwrap(), blobs[1]);
+    assert_eq!(reader.string().unwrap(), b"test-rsa");
+    assert!(reader.0.is_empty());
+  }
+
+  #[tokio::test]
+  async fn signs_with_ed25519() {
+    let key = ed25519();
+    let public_key = key.public_key().clone();
+    let state = state(vec![agent_key("a", key)]);
+
+    let request = sign_request(&public_key.to_bytes().unwrap(), b"data", 0);
+    let reply = ask(&state, &AgentOptions::default(), true, &request).await;
+    let (algorithm, blob) = read_signature(&reply);
+    assert_eq!(algorithm, "ssh-ed25519");
+
+    let signature = Signature::new(Algorithm::Ed25519, blob).unwrap();
+    assert!(public_key.key_data().verify(b"data", &signature).is_ok());
+  }
+
+  #[tokio::test]
+  async fn signs_with_rsa_sha2_only() {
+    let key = rsa();
+    let public_key = key.public_key().clone();
+    let blob = public_key.to_bytes().unwrap();
+    let state = state(vec![agent_key("a", key)]);
+    let options = AgentOptions::default();
+
+    for (flags, hash, name) in [
+      (RSA_SHA2_512, HashAlg::Sha512, "rsa-sha2-512"),
+      (RSA_SHA2_256, HashAlg::Sha256, "rsa-sha2-256"),
+    ] {
+      let reply = ask(&state, &options, true, &sign_request(&blob, b"data", flags)).await;
+      let (algorithm, signature) = read_signature(&reply);
+      assert_eq!(algorithm, name);
+
+      let signature = Signature::new(Algorithm::Rsa { hash: Some(hash) }, signature).unwrap();
+      assert!(public_key.key_data().verify(b"data", &signature).is_ok());
+    }
+
+    let reply = ask(&state, &options, true, &sign_request(&blob, b"data", 0)).await;
+    assert_eq!(reply, [FAILURE]);
+  }
+
+  // Against material made outside this crate: the public key comes from
+  // `ssh-keygen -y` and the signature from `openssl dgst -sha256 -sign`.
+  #[tokio::test]
+  async fn rsa_signatures_match_openssh_and_openssl() {
+    let public_key =
+      ssh_key::PublicKey::from_openssh(include_str!("../../testdata/agent_rsa_key.pub")).unwrap();
+    let blob = public_key.to_bytes().unwrap();
+    let state = state(vec![agent_key("a", rsa())]);
+    let options = AgentOptions::default();
+
+    let request = sign_request(&blob, b"data", RSA_SHA2_256);
+    let reply = ask(&state, &options, true, &request).await;
+    let (_, signature) = read_signature(&reply);
+    // PKCS#1 v1.5 is deterministic, so the bytes must match exactly.
+    assert_eq!(
+      signature,
+      include_bytes!("../../testdata/agent_rsa_key.sha256.sig")
+    );
+
+    let request = sign_request(&blob, b"data", RSA_SHA2_512);
+    let reply = ask(&state, &options, true, &request).await;
+    let (_, signature) = read_signature(&reply);
+    let signature = Signature::new(
+      Algorithm::Rsa {
+        hash: Some(HashAlg::Sha512),
+      },
+      signature,
+    )
+    .unwrap();
+    assert!(public_key.key_data().verify(b"data", &signature).is_ok());
+  }
+
+  #[tokio::test]
+  async fn refuses_what_it_cannot_answer() {
+    let key = ed25519();
+    let blob = key.public_key().to_bytes().unwrap();
+    let state = state(vec![agent_key("a", key)]);
+    let options = AgentOptions {
+      confirm: vec!["a".into()],
+      idle_timeout: None,
+    };
+
+    // Declined confirmation, unknown key, truncated request, empty and
+    // unsupported messages.
+    let request = sign_request(&blob, b"data", 0);
+    assert_eq!(ask(&state, &options, false, &request).await, [FAILURE]);
+    assert_eq!(
+      read_signature(&ask(&state, &options, true, &request).await).0,
+      "ssh-ed25519"
+    );
+
+    let unknown = sign_request(&ed25519().public_key().to_bytes().unwrap(), b"data", 0);
+    assert_eq!(ask(&state, &options, true, &unknown).await, [FAILURE]);
+    assert_eq!(
+      ask(&state, &options, true, &request[..request.len() - 2]).await,
+      [FAILURE]
+    );
+    assert_eq!(ask(&state, &options, true, &[]).await, [FAILURE]);
+    assert_eq!(ask(&state, &options, true, &[99]).await, [FAILURE]);
+  }
+
+  #[tokio::test]
+  async fn lock_forgets_the_keys() {
+    let state = state(vec![agent_key("a", ed25519())]);
+    let options = AgentOptions::default();
+
+    assert_eq!(ask(&state, &options, true, &[LOCK]).await, [SUCCESS]);
+    assert_eq!(
+      ask(&state, &options, true, &[REQUEST_IDENTITIES]).await,
+      [IDENTITIES_ANSWER, 0, 0, 0, 0]
+    );
+  }
+}
@@ -1,12 +1,79 @@
-use crate::core::models::{Claims, DecryptedSecret, SecretPayload};
-use crate::core::{client::GopherClient, crypto, models::Secret};
-use anyhow::{Context, Result};
+use crate::core::bundle::{self, Identity};
+use crate::core::client::{GopherClient, SessionExpired};
+use crate::core::crypto::{AuthenticationFailed, Cipher, CipherKey, KdfParams, KeyRing, Padding};
+use crate::core::keyfile::Keyfile;
+use crate::core::manifest::{self, IntegrityIssue, Manifest};
+use crate::core::models::{
+  Claims, CustomField, DecryptedSecret, FILE_CHUNK_TYPE, FILE_TYPE, IDENTITY_ID, IDENTITY_TYPE,
+  IdentityKey, MANIFEST_ID, MANIFEST_TYPE, SecretPayload, ShareBundle, SyncReport, VAULT_HEADER_ID,
+  VAULT_HEADER_TYPE, VaultHeader,
+};
+use crate::core::recovery::RecoveryKey;
+use crate::core::secret::{SecretString, Sensitive};
+use crate::core::{crypto, models::Secret};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use zeroize::Zeroizing;
+
+// Known plaintext sealed under the vault key in the vault header. Opening it is
+// how a typed master password is checked before any entry is touched.
+const CANARY: &str = "gophkeeper-canary";
+
+// Plaintext per file chunk record; also the most of a file held in memory.
+const FILE_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct WrongPassword;
+
+impl fmt::Display for WrongPassword {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Wrong master password")
+  }
+}
+
+impl std::error::Error for WrongPassword {}
+
+#[derive(Debug)]
+pub struct KeyfileRequired;
+
+impl fmt::Display for KeyfileRequired {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("This vault also needs its keyfile to unlock")
+  }
+}
+
+impl std::error::Error for KeyfileRequired {}
+
+// What a rotation of the vault key wrapping changes.
+struct Rotation<'a> {
+  pass: &'a str,
+  new_pass: &'a str,
+  keyfile: Option<&'a Keyfile>,
+  // Moves the vault to a fresh key for this cipher instead of re-wrapping the
+  // current one.
+  cipher: Option<Cipher>,
+}
 
 pub struct GopherApp {
   api: GopherClient,
   token: Option<String>,
-  master_password: Option<String>,
+  keys: Option<KeyRing>,
+  kdf_params: KdfParams,
+  cipher: Cipher,
+  padding: Padding,
+  keyfile: Option<Keyfile>,
+  // Taken from the vault header whenever it is read, so a front end can ask
+  // for the keyfile before the next attempt.
+  keyfile_required: bool,
   current_user: Option<String>,
+  // Expiry of the session token, in seconds since the epoch.
+  session_expires: Option<u64>,
 }
 use jsonwebtoken;
 
@@ -15,41 +82,110 @@ impl GopherApp {
     Self {
       api: GopherClient::new(base_url),
       token: None,
-      master_password: None,
+      keys: None,
+      kdf_params: KdfParams::default(),
+      cipher: Cipher::default(),
+      padding: Padding::default(),
+      keyfile: None,
+      keyfile_required: false,
       current_user: None,
+      session_expires: None,
     }
   }
 
-  pub async fn register(&self, login: String, pass: String) -> Result<()> {
-    self.api.register(&login, &pass).await
+  pub async fn register(&self, login: String, pass: SecretString) -> Result<()> {
+    let auth = SecretString::new(crypto::derive_auth_hash(&login, &pass)?);
+    self.api.register(&login, &auth).await
   }
 
-  pub async fn login(&mut self, login: String, pass: String) -> Result<()> {
-    let token = self.api.login(&login, &pass).await?;
+  pub async fn login(&mut self, login: String, pass: SecretString) -> Result<()> {
+    let auth = SecretString::new(crypto::derive_auth_hash(&login, &pass)?);
+    let token = self.api.login(&login, &auth).await?;
+
+    self.start_session(token, login);
+    self.unlock(pass).await
+  }
+
+  // Accounts created before the login password was split from the vault key
+  // authenticate with the raw password. Log in that way once and replace it
+  // on the server with the derived authentication hash.
+  pub async fn migrate_account(&mut self, login: String, pass: SecretString) -> Result<()> {
+    let token = self
+      .api
+      .login(&login, &pass)
+      .await
+      .context("Login with the legacy password failed")?;
+
+    let auth = SecretString::new(crypto::derive_auth_hash(&login, &pass)?);
+    self.api.change_password(&token, &pass, &auth).await?;
 
+    self.start_session(token, login);
+    self.unlock(pass).await
+  }
+
+  // The expiry is read without checking the signature: the server does that on
+  // every request, this only saves a round trip with a token it would refuse.
+  fn token_expiry(token: &str) -> Option<u64> {
+    jsonwebtoken::dangerous::insecure_decode::<Claims>(token)
+      .ok()
+      .map(|data| data.claims.exp as u64)
+  }
+
+  fn start_session(&mut self, token: String, login: String) {
+    self.session_expires = Self::token_expiry(&token);
     self.token = Some(token);
-    self.master_password = Some(pass);
     self.current_user = Some(login);
-
-    Ok(())
   }
 
-  pub async fn try_auto_login(&mut self) -> Result<()> {
-    let token = GopherClient::load_token().await?;
+  // Returns false when there is no stored session. A stored token past its
+  // expiry is dropped, but the login is kept so the front end can ask for the
+  // password only.
+  pub async fn try_auto_login(&mut self) -> Result<bool> {
+    let Some((token, claims)) = self.api.load_token().await? else {
+      return Ok(false);
+    };
+
+    let expires = claims.exp as u64;
+    self.current_user = Some(claims.login);
 
-    let token_data = jsonwebtoken::dangerous::insecure_decode::<Claims>(&token)?;
+    if expires <= now() {
+      self.expire_session().await?;
+      return Err(SessionExpired.into());
+    }
 
-    self.current_user = Some(token_data.claims.login);
     self.token = Some(token);
+    self.session_expires = Some(expires);
+    Ok(true)
+  }
+
+  // Called by front ends when the server answers with SessionExpired. Like
+  // logout, but keeps the login for the next attempt.
+  pub async fn expire_session(&mut self) -> Result<()> {
+    self.token = None;
+    self.keys = None;
+    self.session_expires = None;
+
+    if let Some(login) = &self.current_user {
+      self.api.delete_token(login).await?;
+    }
     Ok(())
   }
 
+  pub fn session_remaining(&self) -> Option<Duration> {
+    let expires = self.session_expires.filter(|_| self.token.is_some())?;
+    Some(Duration::from_secs(expires.saturating_sub(now())))
+  }
+
   pub async fn logout(&mut self) -> Result<()> {
     self.token = None;
-    self.master_password = None;
-    self.current_user = None;
+    self.keys = None;
+    self.keyfile = None;
+    self.keyfile_required = false;
+    self.session_expires = None;
 
-    GopherClient::delete_token().await?;
+    if let Some(login) = self.current_user.take() {
+      self.api.delete_token(&login).await?;
+    }
     Ok(())
   }
 
@@ -57,12 +193,34 @@ impl GopherApp {
     self.token.is_some()
   }
 
-  pub fn master_password_is_none(&self) -> bool {
-    self.master_password.is_none()
+  pub fn is_unlocked(&self) -> bool {
+    self.keys.is_some()
   }
 
-  pub fn set_master_password(&mut self, pass: String) {
-    self.master_password = Some(pass);
+  // Forgets the vault key but keeps the session, so unlock works again
+  // without logging in.
+  pub fn lock(&mut self) {
+    self.keys = None;
+  }
+
+  pub fn set_kdf_params(&mut self, params: KdfParams) {
+    self.kdf_params = params;
+  }
+
+  pub fn set_cipher(&mut self, cipher: Cipher) {
+    self.cipher = cipher;
+  }
+
+  pub fn set_padding(&mut self, padding: Padding) {
+    self.padding = padding;
+  }
+
+  pub fn set_keyfile(&mut self, keyfile: Option<Keyfile>) {
+    self.keyfile = keyfile;
+  }
+
+  pub fn requires_keyfile(&self) -> bool {
+    self.keyfile_required
   }
 
   pub fn get_current_user(&self) -> Option<&str> {
@@ -77,25 +235,606 @@ impl GopherApp {
     self.current_user = None
   }
 
-  pub async fn sync_and_decrypt(&self) -> Result<Vec<DecryptedSecret>> {
+  pub async fn unlock(&mut self, pass: SecretString) -> Result<()> {
+    let token = self.token.clone().context("Not logged in")?;
+    let secrets = self.api.get_secrets(&token).await?;
+
+    let header = Self::read_header(&secrets)?;
+    self.keyfile_required = header.as_ref().is_some_and(|h| h.keyfile);
+
+    let keys = match header {
+      Some(header) => self.open_vault(&token, &pass, header, &secrets).await?,
+      None => self.init_vault(&token, &pass, &secrets).await?,
+    };
+
+    self.keys = Some(keys);
+    Ok(())
+  }
+
+  fn read_header(secrets: &[Secret]) -> Result<Option<VaultHeader>> {
+    secrets
+      .iter()
+      .find(|s| s.id == VAULT_HEADER_ID && s.secret_type == VAULT_HEADER_TYPE)
+      .map(|sec| serde_json::from_str(&sec.data).context("Invalid vault header"))
+      .transpose()
+  }
+
+  fn recovery_context(&self) -> Vec<u8> {
+    crypto::associated_data(
+      VAULT_HEADER_ID,
+      "recovery",
+      self.current_user.as_deref().unwrap_or_default(),
+    )
+  }
+
+  fn header_context(&self) -> Vec<u8> {
+    crypto::associated_data(
+      VAULT_HEADER_ID,
+      VAULT_HEADER_TYPE,
+      self.current_user.as_deref().unwrap_or_default(),
+    )
+  }
+
+  fn canary_context(&self) -> Vec<u8> {
+    crypto::associated_data(
+      VAULT_HEADER_ID,
+      "canary",
+      self.current_user.as_deref().unwrap_or_default(),
+    )
+  }
+
+  fn header_record(&self, header: &VaultHeader) -> Result<Secret> {
+    Ok(Secret {
+      id: VAULT_HEADER_ID.into(),
+      user_login: self.current_user.clone().unwrap_or_default(),
+      secret_type: VAULT_HEADER_TYPE.into(),
+      data: serde_json::to_string(header)?,
+      created_at: None,
+      updated_at: None,
+    })
+  }
+
+  fn seal_header(
+    &self,
+    pass: &str,
+    data_key: &CipherKey,
+    keyfile: Option<&Keyfile>,
+  ) -> Result<(VaultHeader, CipherKey)> {
+    let salt = crypto::generate_salt();
+    let master =
+      CipherKey::derive(pass, &salt, &self.kdf_params, keyfile)?.with_cipher(data_key.cipher());
+
+    let header = VaultHeader {
+      kdf: self.kdf_params,
+      cipher: data_key.cipher(),
+      salt: STANDARD.encode(&salt),
+      wrapped_key: Some(crypto::wrap_key(data_key, &master, &self.header_context())?),
+      canary: Some(crypto::encrypt_string(
+        CANARY,
+        data_key,
+        &self.canary_context(),
+      )?),
+      keyfile: keyfile.is_some(),
+      recovery_key: None,
+    };
+
+    Ok((header, master))
+  }
+
+  async fn open_vault(
+    &self,
+    token: &str,
+    pass: &str,
+    mut header: VaultHeader,
+    secrets: &[Secret],
+  ) -> Result<KeyRing> {
+    let keyfile = match (header.keyfile, &self.keyfile) {
+      (true, None) => return Err(KeyfileRequired.into()),
+      (false, Some(_)) => bail!("This vault does not use a keyfile"),
+      (_, keyfile) => keyfile.as_ref(),
+    };
+
+    let salt = STANDARD
+      .decode(&header.salt)
+      .context("Invalid vault salt")?;
+    let master = CipherKey::derive(pass, &salt, &header.kdf, keyfile)?.with_cipher(header.cipher);
+    let mut dirty = false;
+
+    let data_key = match &header.wrapped_key {
+      Some(wrapped) => {
+        crypto::unwrap_key(wrapped, &master, &self.header_context()).map_err(|_| WrongPassword)?
+      }
+      None => {
+        // Vaults created before the key hierarchy encrypted entries with the
+        // password-derived key directly. Give them a vault key now; the entries
+        // themselves move over lazily on sync.
+        let keys = KeyRing::new(master.clone()).with_legacy(crypto::legacy_key(pass));
+        self.check_password(&keys, secrets)?;
+
+        let data_key = CipherKey::generate();
+        header.wrapped_key = Some(crypto::wrap_key(
+          &data_key,
+          &master,
+          &self.header_context(),
+        )?);
+        dirty = true;
+        data_key
+      }
+    }
+    .with_cipher(header.cipher);
+
+    match &header.canary {
+      Some(canary) => {
+        let opened = crypto::decrypt_string(
+          canary,
+          &KeyRing::new(data_key.clone()),
+          &self.canary_context(),
+        )
+        .map(Sensitive::new)
+        .map_err(|_| WrongPassword)?;
+
+        if opened.as_str() != CANARY {
+          return Err(WrongPassword.into());
+        }
+      }
+      None => {
+        header.canary = Some(crypto::encrypt_string(
+          CANARY,
+          &data_key,
+          &self.canary_context(),
+        )?);
+        dirty = true;
+      }
+    }
+
+    if dirty {
+      self
+        .api
+        .add_secret(token, vec![self.header_record(&header)?])
+        .await?;
+    }
+
+    Ok(
+      KeyRing::new(data_key)
+        .with_key(master)
+        .with_legacy(crypto::legacy_key(pass)),
+    )
+  }
+
+  async fn init_vault(&self, token: &str, pass: &str, secrets: &[Secret]) -> Result<KeyRing> {
+    let data_key = CipherKey::generate().with_cipher(self.cipher);
+    let (header, master) = self.seal_header(pass, &data_key, self.keyfile.as_ref())?;
+
+    let keys = KeyRing::new(data_key)
+      .with_key(master)
+      .with_legacy(crypto::legacy_key(pass));
+
+    // Entries left by an older client are re-encrypted lazily on sync, but the
+    // password still has to open them before we commit to a new vault header.
+    self.check_password(&keys, secrets)?;
+
+    self
+      .api
+      .add_secret(token, vec![self.header_record(&header)?])
+      .await?;
+
+    Ok(keys)
+  }
+
+  fn check_password(&self, keys: &KeyRing, secrets: &[Secret]) -> Result<()> {
+    let owner = self.current_user.as_deref().unwrap_or_default();
+
+    if let Some(sec) = secrets.iter().find(|s| !s.is_control()) {
+      let context = crypto::associated_data(&sec.id, &sec.secret_type, owner);
+      crypto::decrypt_string(&sec.data, keys, &context).map_err(|_| WrongPassword)?;
+    }
+
+    Ok(())
+  }
+
+  // Entries are encrypted under the random vault key, so a new password only
+  // re-wraps that key in the vault header. Entries still sealed directly with
+  // the old password-derived key are moved to the vault key first.
+  pub async fn change_master_password(
+    &mut self,
+    old: SecretString,
+    new: SecretString,
+  ) -> Result<()> {
+    let rotation = Rotation {
+      pass: &old,
+      new_pass: &new,
+      keyfile: self.keyfile.as_ref(),
+      cipher: None,
+    };
+    let (keys, _) = self.rotate(rotation).await?;
+    self.keys = Some(keys);
+    Ok(())
+  }
+
+  // Requires `keyfile` next to the master password from now on, or stops
+  // requiring one if it is `None`. The vault is opened with the keyfile set
+  // before. Like a password change this only re-wraps the vault key.
+  pub async fn change_keyfile(
+    &mut self,
+    pass: SecretString,
+    keyfile: Option<Keyfile>,
+  ) -> Result<()> {
+    let rotation = Rotation {
+      pass: &pass,
+      new_pass: &pass,
+      keyfile: keyfile.as_ref(),
+      cipher: None,
+    };
+    let (keys, _) = self.rotate(rotation).await?;
+    self.keyfile_required = keyfile.is_some();
+    self.keyfile = keyfile;
+    self.keys = Some(keys);
+    Ok(())
+  }
+
+  // Moves every entry to a fresh vault key used with the given cipher. The
+  // password is needed to wrap the new key into the vault header. Returns
+  // whether a recovery key existed, as it no longer opens the new vault key.
+  pub async fn convert_cipher(&mut self, pass: SecretString, cipher: Cipher) -> Result<bool> {
+    let rotation = Rotation {
+      pass: &pass,
+      new_pass: &pass,
+      keyfile: self.keyfile.as_ref(),
+      cipher: Some(cipher),
+    };
+    let (keys, dropped_recovery) = self.rotate(rotation).await?;
+    self.keys = Some(keys);
+    Ok(dropped_recovery)
+  }
+
+  // Opens the vault with the current password, wraps the vault key (or a new
+  // one) for the new password and keyfile, and re-encrypts whatever has to
+  // move. Everything is uploaded together and read back; on any failure the
+  // previous records are put back. Returns the new key ring and whether a
+  // recovery key was dropped along with the old vault key.
+  async fn rotate(&self, rotation: Rotation<'_>) -> Result<(KeyRing, bool)> {
+    let token = self.token.clone().context("Not logged in")?;
+    let owner = self.current_user.clone().context("Not logged in")?;
+
+    // Read once: the entries check the password of a vault from before the
+    // vault key, and the header as read here is what a roll back restores,
+    // even if opening the vault has already upgraded it on the server.
+    let original = self.api.get_secrets(&token).await?;
+    let header = Self::read_header(&original)?.context("Vault is not initialised")?;
+    if rotation.cipher == Some(header.cipher) {
+      bail!("Vault already uses {}", header.cipher.id());
+    }
+    let recovery_key = header.recovery_key.clone();
+    let old_keys = self
+      .open_vault(&token, rotation.pass, header, &original)
+      .await?;
+
+    // A new cipher means a new vault key, and every entry moves under it.
+    let (data_key, outdated_only) = match rotation.cipher {
+      Some(cipher) => (CipherKey::generate().with_cipher(cipher), false),
+      None => (old_keys.primary().clone(), true),
+    };
+    let mut rotated = self.reencrypt(&original, &old_keys, &data_key, outdated_only)?;
+
+    let (mut header, master) = self.seal_header(rotation.new_pass, &data_key, rotation.keyfile)?;
+    let dropped_recovery = recovery_key.is_some() && rotation.cipher.is_some();
+    if rotation.cipher.is_none() {
+      header.recovery_key = recovery_key;
+    }
+    rotated.push(self.header_record(&header)?);
+
+    let new_keys = KeyRing::new(data_key).with_key(master);
+    let manifest = self
+      .next_manifest(&original, old_keys.primary(), &rotated)
+      .await?;
+    rotated.push(self.manifest_record(&manifest, new_keys.primary())?);
+    let touched: Vec<String> = rotated.iter().map(|s| s.id.clone()).collect();
+
+    // The login password is derived from the master password, so it follows.
+    let new_login = if rotation.pass != rotation.new_pass {
+      Some((
+        SecretString::new(crypto::derive_auth_hash(&owner, rotation.pass)?),
+        SecretString::new(crypto::derive_auth_hash(&owner, rotation.new_pass)?),
+      ))
+    } else {
+      None
+    };
+
+    let mut result = self
+      .commit_rotation(&token, &owner, rotated, &new_keys)
+      .await;
+    if let (Ok(()), Some((old_auth, new_auth))) = (&result, &new_login) {
+      result = self
+        .api
+        .change_password(&token, old_auth, new_auth)
+        .await
+        .context("Failed to update login credentials");
+    }
+    // The server would otherwise still let the old recovery key reset the
+    // login password, leaving a vault that no key opens. Revoked last: until
+    // then the old vault, which the key does open, may still be put back.
+    if result.is_ok() && dropped_recovery {
+      result = self
+        .api
+        .clear_recovery(&token)
+        .await
+        .context("Failed to revoke the recovery key");
+    }
+
+    if let Err(e) = result {
+      return Err(self.roll_back(&token, original, &touched, e).await);
+    }
+
+    self.save_manifest(&manifest).await?;
+    Ok((new_keys, dropped_recovery))
+  }
+
+  // Seals the vault key under a newly generated recovery key and registers its
+  // proof with the server, replacing any earlier recovery key. The old proof is
+  // revoked first: a failure part way then leaves no recovery key rather than
+  // one the server accepts but the vault header does not.
+  pub async fn create_recovery_key(&self) -> Result<RecoveryKey> {
+    let token = self.token.as_ref().context("Not logged in")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+
+    let secrets = self.api.get_secrets(token).await?;
+    let mut header = Self::read_header(&secrets)?.context("Vault is not initialised")?;
+
+    // The recovery key only opens the vault key, so entries still sealed with
+    // an older key have to move under it first.
+    let upgrades = self.reencrypt(&secrets, keys, keys.primary(), true)?;
+    if !upgrades.is_empty() {
+      self
+        .publish(token, keys.primary(), upgrades)
+        .await
+        .context("Failed to upgrade legacy entries")?;
+    }
+
+    let recovery = RecoveryKey::generate();
+    header.recovery_key = Some(crypto::wrap_key(
+      keys.primary(),
+      &recovery.cipher_key().with_cipher(header.cipher),
+      &self.recovery_context(),
+    )?);
+
+    self.api.clear_recovery(token).await?;
+    self
+      .api
+      .add_secret(token, vec![self.header_record(&header)?])
+      .await
+      .context("The old recovery key was revoked and the new one was not stored; no recovery key is active")?;
+    self
+      .api
+      .set_recovery(token, &recovery.auth_hash())
+      .await
+      .context("The old recovery key was revoked and the new one was not registered; no recovery key is active")?;
+
+    Ok(recovery)
+  }
+
+  // Sets a new master password with the recovery key instead of the old one.
+  // The server resets the login password first; if the vault header cannot be
+  // updated afterwards, running recovery again with the same key finishes it.
+  // The new header takes the keyfile set on this app, if any, so recovery also
+  // gets past a lost keyfile.
+  pub async fn recover(
+    &mut self,
+    login: String,
+    recovery: RecoveryKey,
+    new: SecretString,
+  ) -> Result<()> {
+    let auth = SecretString::new(crypto::derive_auth_hash(&login, &new)?);
+
+    let token = self
+      .api
+      .recover(&login, &recovery.auth_hash(), &auth)
+      .await?;
+    self.start_session(token.clone(), login);
+
+    let header = Self::read_header(&self.api.get_secrets(&token).await?)?
+      .context("Vault is not initialised")?;
+    let wrapped = header
+      .recovery_key
+      .as_deref()
+      .context("Vault has no recovery key")?;
+
+    let data_key = crypto::unwrap_key(wrapped, &recovery.cipher_key(), &self.recovery_context())
+      .map_err(|_| anyhow!("Recovery key does not open this vault"))?
+      .with_cipher(header.cipher);
+
+    let (mut new_header, master) = self.seal_header(&new, &data_key, self.keyfile.as_ref())?;
+    new_header.recovery_key = header.recovery_key.clone();
+
+    self
+      .api
+      .add_secret(&token, vec![self.header_record(&new_header)?])
+      .await
+      .context("Login password was reset but the vault was not; run recovery again")?;
+
+    self.keyfile_required = new_header.keyfile;
+    self.keys = Some(KeyRing::new(data_key).with_key(master));
+    Ok(())
+  }
+
+  // Re-encrypts the vault entries under `to`. With `outdated_only`, entries
+  // already in the current format under the primary key are left alone.
+  fn reencrypt(
+    &self,
+    secrets: &[Secret],
+    from: &KeyRing,
+    to: &CipherKey,
+    outdated_only: bool,
+  ) -> Result<Vec<Secret>> {
+    let owner = self.current_user.as_deref().unwrap_or_default();
+
+    let mut rotated = Vec::new();
+    for sec in secrets {
+      if sec.is_control() || outdated_only && !from.needs_upgrade(&sec.data)? {
+        continue;
+      }
+
+      let context = crypto::associated_data(&sec.id, &sec.secret_type, owner);
+      let json = crypto::decrypt_string(&sec.data, from, &context)
+        .map(Sensitive::new)
+        .with_context(|| format!("Current password does not open secret {}", sec.id))?;
+
+      rotated.push(Secret {
+        data: crypto::encrypt_string(&Sensitive::new(self.padding.apply(&json)), to, &context)?,
+        updated_at: None,
+        ..sec.clone()
+      });
+    }
+
+    Ok(rotated)
+  }
+
+  async fn roll_back(
+    &self,
+    token: &str,
+    original: Vec<Secret>,
+    touched: &[String],
+    e: anyhow::Error,
+  ) -> anyhow::Error {
+    let added: Vec<&String> = touched
+      .iter()
+      .filter(|id| !original.iter().any(|sec| sec.id == **id))
+      .collect();
+    let restore = original
+      .into_iter()
+      .filter(|sec| touched.contains(&sec.id))
+      .map(|sec| Secret {
+        updated_at: None,
+        ..sec
+      })
+      .collect();
+
+    let mut restored = self.api.add_secret(token, restore).await;
+    for id in added {
+      if restored.is_ok() {
+        restored = self.api.delete_secret(token, id).await;
+      }
+    }
+
+    match restored {
+      Ok(()) => e.context("Vault change rolled back"),
+      Err(restore_err) => e.context(format!(
+        "Vault change failed and the previous vault could not be restored: {}",
+        restore_err
+      )),
+    }
+  }
+
+  // The server applies multi-secret uploads row by row, so read everything back
+  // and make sure no entry was left under the old key.
+  async fn commit_rotation(
+    &self,
+    token: &str,
+    owner: &str,
+    rotated: Vec<Secret>,
+    keys: &KeyRing,
+  ) -> Result<()> {
+    let expected: Vec<(String, String)> = rotated
+      .iter()
+      .map(|s| (s.id.clone(), s.data.clone()))
+      .collect();
+
+    self
+      .api
+      .add_secret(token, rotated)
+      .await
+      .context("Failed to upload re-encrypted vault")?;
+
+    let stored = self.api.get_secrets(token).await?;
+
+    for (id, data) in expected {
+      let sec = stored
+        .iter()
+        .find(|s| s.id == id)
+        .with_context(|| format!("Secret {} missing after re-encryption", id))?;
+
+      if sec.data != data {
+        return Err(anyhow!("Secret {} was not re-encrypted", id));
+      }
+
+      if !sec.is_control() {
+        let context = crypto::associated_data(&sec.id, &sec.secret_type, owner);
+        crypto::decrypt_string(&sec.data, keys, &context)
+          .with_context(|| format!("Secret {} does not open under the new key", id))?;
+      }
+    }
+
+    Ok(())
+  }
+
+  pub async fn sync_and_decrypt(&self) -> Result<SyncReport> {
     let token = self.token.as_ref().context("Not logged in")?;
-    let master = self
-      .master_password
-      .as_ref()
-      .context("Master password not set")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+    let owner = self.current_user.as_deref().unwrap_or_default();
 
     let secrets = self.api.get_secrets(token).await?;
 
-    let mut result = Vec::new();
+    let mut report = SyncReport::default();
+    let mut upgrades = Vec::new();
+
+    let (mut manifest, mut issues) = self.trusted_manifest(&secrets, keys.primary()).await?;
+    issues.extend(manifest.check(&secrets));
+    let adopted = manifest.adopt(&secrets);
+    report.issues = issues;
 
     for sec in secrets {
-      let decrypted_json =
-        crypto::decrypt_string(&sec.data, master).context("Decryption failed")?;
+      if sec.is_control() || sec.secret_type == IDENTITY_TYPE {
+        continue;
+      }
+
+      let context = crypto::associated_data(&sec.id, &sec.secret_type, owner);
+      // One bad entry is reported with the others rather than hiding the
+      // rest of the vault. The keys passed the canary check at unlock, so an
+      // entry they fail to authenticate was changed on the server.
+      let opened = crypto::decrypt_string(&sec.data, keys, &context)
+        .map(Sensitive::new)
+        .and_then(|json| {
+          let payload: Sensitive<SecretPayload> = serde_json::from_str(Padding::strip(&json))
+            .map(Sensitive::new)
+            .context("Invalid secret format")?;
+          Ok((json, payload))
+        });
+      let (decrypted_json, payload) = match opened {
+        Ok(opened) => opened,
+        Err(e) if e.is::<AuthenticationFailed>() => {
+          report.issues.push(IntegrityIssue::Tampered(sec.id));
+          continue;
+        }
+        Err(e) => {
+          report.issues.push(IntegrityIssue::Unreadable {
+            id: sec.id,
+            reason: format!("{:#}", e),
+          });
+          continue;
+        }
+      };
 
-      let payload: SecretPayload =
-        serde_json::from_str(&decrypted_json).context("Invalid secret format")?;
+      // Re-encrypting a flagged entry would make it look current.
+      let flagged = report
+        .issues
+        .iter()
+        .any(|i| matches!(i, IntegrityIssue::Stale(id) if *id == sec.id));
 
-      result.push(DecryptedSecret {
+      if keys.needs_upgrade(&sec.data)? && !flagged {
+        upgrades.push(Secret {
+          id: sec.id.clone(),
+          user_login: sec.user_login.clone(),
+          secret_type: sec.secret_type.clone(),
+          data: crypto::encrypt_string(
+            &Sensitive::new(self.padding.apply(&decrypted_json)),
+            keys.primary(),
+            &context,
+          )?,
+          created_at: sec.created_at.clone(),
+          updated_at: None,
+        });
+      }
+
+      report.secrets.push(DecryptedSecret {
         id: sec.id,
         secret_type: sec.secret_type,
         payload,
@@ -104,39 +843,516 @@ impl GopherApp {
       });
     }
 
-    Ok(result)
+    if adopted || !upgrades.is_empty() {
+      report.upgraded = upgrades.iter().map(|s| s.id.clone()).collect();
+      for sec in &upgrades {
+        manifest.record_write(sec);
+      }
+      manifest.revision += 1;
+      upgrades.push(self.manifest_record(&manifest, keys.primary())?);
+
+      self
+        .api
+        .add_secret(token, upgrades)
+        .await
+        .context("Failed to upgrade legacy entries")?;
+    }
+    self.save_manifest(&manifest).await?;
+
+    Ok(report)
   }
 
   pub async fn add_secret(&self, payload: SecretPayload) -> Result<()> {
+    self.store_entry(crypto::generate_id(), payload).await
+  }
+
+  async fn store_entry(&self, id: String, payload: SecretPayload) -> Result<()> {
     let token = self.token.as_ref().context("Not logged in")?;
-    let master = self
-      .master_password
-      .as_ref()
-      .context("Master password not set")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
 
-    let json = serde_json::to_string(&payload)?;
-    let encrypted = crypto::encrypt_string(&json, master)?;
+    let payload = Sensitive::new(payload);
+    let json = Sensitive::new(serde_json::to_string(payload.expose())?);
+    let padded = Sensitive::new(self.padding.apply(&json));
+
+    let user_login = self.current_user.clone().unwrap_or_default();
+    let secret_type = match payload.expose() {
+      SecretPayload::Password { .. } => "password",
+      SecretPayload::Note { .. } => "note",
+      SecretPayload::Card { .. } => "card",
+      SecretPayload::Totp { .. } => "totp",
+      SecretPayload::SshKey { .. } => "ssh-key",
+      SecretPayload::File { .. } => FILE_TYPE,
+    };
+
+    let context = crypto::associated_data(&id, secret_type, &user_login);
+    let encrypted = crypto::encrypt_string(&padded, keys.primary(), &context)?;
 
     let secret = Secret {
-      id: crypto::generate_id(&json),
-      user_login: self.current_user.clone().unwrap_or_default(),
-      secret_type: match &payload {
-        SecretPayload::Password { .. } => "password".into(),
-        SecretPayload::Note { .. } => "note".into(),
-        SecretPayload::Card { .. } => "card".into(),
-      },
+      id,
+      user_login,
+      secret_type: secret_type.into(),
       data: encrypted,
       created_at: None,
       updated_at: None,
     };
 
-    self.api.add_secret(token, vec![secret]).await?;
+    self.publish(token, keys.primary(), vec![secret]).await
+  }
 
-    Ok(())
+  // Streams a file into the vault one chunk at a time, each sealed under a
+  // fresh key that only the file's entry holds. The entry goes up last, so a
+  // failed upload leaves nothing visible behind.
+  pub async fn add_file(
+    &self,
+    path: &Path,
+    title: Option<String>,
+    fields: Vec<CustomField>,
+  ) -> Result<()> {
+    let token = self.token.as_ref().context("Not logged in")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+    let owner = self.current_user.as_deref().unwrap_or_default();
+
+    let name = path
+      .file_name()
+      .and_then(|n| n.to_str())
+      .with_context(|| format!("Invalid file name: {}", path.display()))?
+      .to_string();
+    let mut file = fs::File::open(path)
+      .await
+      .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let id = crypto::generate_id();
+    let file_key = CipherKey::generate().with_cipher(keys.primary().cipher());
+    let mut buffer = Zeroizing::new(vec![0u8; FILE_CHUNK_SIZE]);
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    let mut chunks = 0u32;
+
+    let uploaded: Result<()> = async {
+      loop {
+        let filled = read_chunk(&mut file, &mut buffer).await?;
+        // An empty file still gets one empty chunk.
+        if filled == 0 && chunks > 0 {
+          break;
+        }
+        hasher.update(&buffer[..filled]);
+        size += filled as u64;
+
+        let chunk_id = file_chunk_id(&id, chunks);
+        let context = crypto::associated_data(&chunk_id, FILE_CHUNK_TYPE, owner);
+        let record = Secret {
+          data: crypto::encrypt_bytes(&buffer[..filled], &file_key, &context)?,
+          id: chunk_id,
+          user_login: owner.into(),
+          secret_type: FILE_CHUNK_TYPE.into(),
+          created_at: None,
+          updated_at: None,
+        };
+        self.api.add_secret(token, vec![record]).await?;
+        chunks += 1;
+
+        if filled < FILE_CHUNK_SIZE {
+          break;
+        }
+      }
+      Ok(())
+    }
+    .await;
+
+    let stored = match uploaded {
+      Ok(()) => {
+        let payload = SecretPayload::File {
+          title: title.unwrap_or_else(|| name.clone()),
+          name,
+          size,
+          chunks,
+          key: file_key.export().expose().clone(),
+          digest: STANDARD.encode(hasher.finalize()),
+          fields,
+        };
+        self.store_entry(id.clone(), payload).await
+      }
+      Err(e) => Err(e),
+    };
+    if stored.is_err() {
+      for index in 0..chunks {
+        let _ = self
+          .api
+          .delete_secret(token, &file_chunk_id(&id, index))
+          .await;
+      }
+    }
+    stored
+  }
+
+  // Decrypts a file entry chunk by chunk into `out`, which must not exist yet.
+  // The output is removed again unless every chunk is present and the content
+  // matches the recorded size and digest.
+  pub async fn save_file(&self, id: &str, out: &Path) -> Result<u64> {
+    let token = self.token.as_ref().context("Not logged in")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+    let owner = self.current_user.as_deref().unwrap_or_default();
+
+    let sec = self
+      .api
+      .get_secret(token, id)
+      .await?
+      .filter(|s| s.secret_type == FILE_TYPE)
+      .with_context(|| format!("File {} not found", id))?;
+    let payload = self.open_entry(&sec, keys)?;
+    let SecretPayload::File {
+      size,
+      chunks,
+      key,
+      digest,
+      ..
+    } = payload.expose()
+    else {
+      bail!("Secret {} is not a file", id);
+    };
+    let file_key = KeyRing::new(CipherKey::import(key).context("Invalid file key")?);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut output = options
+      .open(out)
+      .await
+      .map_err(|e| anyhow!("Failed to create {}: {}", out.display(), e))?;
+
+    let written: Result<u64> = async {
+      let mut hasher = Sha256::new();
+      let mut total = 0u64;
+      for index in 0..*chunks {
+        let chunk_id = file_chunk_id(id, index);
+        let chunk = self
+          .api
+          .get_secret(token, &chunk_id)
+          .await?
+          .filter(|s| s.secret_type == FILE_CHUNK_TYPE)
+          .with_context(|| format!("Chunk {} of file {} is missing", index, id))?;
+        let context = crypto::associated_data(&chunk_id, FILE_CHUNK_TYPE, owner);
+        let plain = crypto::decrypt_bytes(&chunk.data, &file_key, &context)
+          .map(Zeroizing::new)
+          .with_context(|| format!("Chunk {} of file {} is damaged", index, id))?;
+        hasher.update(&plain);
+        total += plain.len() as u64;
+        output.write_all(&plain).await?;
+      }
+      output.flush().await?;
+
+      if total != *size || STANDARD.encode(hasher.finalize()) != *digest {
+        bail!("File {} does not match its recorded size and digest", id);
+      }
+      Ok(total)
+    }
+    .await;
+
+    if written.is_err() {
+      drop(output);
+      let _ = fs::remove_file(out).await;
+    }
+    written
+  }
+
+  fn open_entry(&self, sec: &Secret, keys: &KeyRing) -> Result<Sensitive<SecretPayload>> {
+    let owner = self.current_user.as_deref().unwrap_or_default();
+    let context = crypto::associated_data(&sec.id, &sec.secret_type, owner);
+    let json = crypto::decrypt_string(&sec.data, keys, &context)
+      .map(Sensitive::new)
+      .map_err(|e| anyhow!("Failed to decrypt secret {}: {:#}", sec.id, e))?;
+    serde_json::from_str(Padding::strip(&json))
+      .map(Sensitive::new)
+      .context("Invalid secret format")
+  }
+
+  fn identity_context(&self) -> Vec<u8> {
+    crypto::associated_data(
+      IDENTITY_ID,
+      IDENTITY_TYPE,
+      self.current_user.as_deref().unwrap_or_default(),
+    )
+  }
+
+  // Reads the X25519 identity from the vault, creating it on first use. It is
+  // an ordinary entry under the vault key, so rotations carry it along.
+  async fn identity(&self) -> Result<Identity> {
+    let token = self.token.as_ref().context("Not logged in")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+
+    let secrets = self.api.get_secrets(token).await?;
+    if let Some(sec) = secrets
+      .iter()
+      .find(|s| s.id == IDENTITY_ID && s.secret_type == IDENTITY_TYPE)
+    {
+      let json = crypto::decrypt_string(&sec.data, keys, &self.identity_context())
+        .map(Sensitive::new)
+        .context("Failed to decrypt identity key")?;
+      let stored: Sensitive<IdentityKey> = serde_json::from_str(Padding::strip(&json))
+        .map(Sensitive::new)
+        .context("Invalid identity key")?;
+      return Identity::from_secret(&stored.secret);
+    }
+
+    let identity = Identity::generate();
+    let stored = Sensitive::new(IdentityKey {
+      secret: identity.to_secret().expose().clone(),
+    });
+    let json = Sensitive::new(serde_json::to_string(stored.expose())?);
+
+    let record = Secret {
+      id: IDENTITY_ID.into(),
+      user_login: self.current_user.clone().unwrap_or_default(),
+      secret_type: IDENTITY_TYPE.into(),
+      data: crypto::encrypt_string(&json, keys.primary(), &self.identity_context())?,
+      created_at: None,
+      updated_at: None,
+    };
+    self.publish(token, keys.primary(), vec![record]).await?;
+
+    Ok(identity)
+  }
+
+  pub async fn public_key(&self) -> Result<String> {
+    Ok(self.identity().await?.public_key())
+  }
+
+  // Seals one entry to `recipient`'s public key. The bundle carries the same
+  // padded payload JSON as a vault entry and needs no server to open.
+  pub async fn export_share(&self, id: &str, recipient: &str) -> Result<ShareBundle> {
+    let token = self.token.as_ref().context("Not logged in")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+    let owner = self.current_user.as_deref().unwrap_or_default();
+
+    let secrets = self.api.get_secrets(token).await?;
+    let sec = secrets
+      .iter()
+      .find(|s| s.id == id && !s.is_control() && s.secret_type != IDENTITY_TYPE)
+      .with_context(|| format!("Secret {} not found", id))?;
+
+    let context = crypto::associated_data(&sec.id, &sec.secret_type, owner);
+    let json = crypto::decrypt_string(&sec.data, keys, &context)
+      .map(Sensitive::new)
+      .map_err(|e| anyhow!("Failed to decrypt secret {}: {:#}", sec.id, e))?;
+    let payload: Sensitive<SecretPayload> = serde_json::from_str(Padding::strip(&json))
+      .map(Sensitive::new)
+      .context("Invalid secret format")?;
+    if let SecretPayload::File { .. } = payload.expose() {
+      bail!("File entries cannot be shared");
+    }
+
+    bundle::seal(&Sensitive::new(self.padding.apply(&json)), recipient)
+  }
+
+  // Opens a bundle sealed to this user's identity and stores its payload as a
+  // new entry.
+  pub async fn import_share(&self, share: &ShareBundle) -> Result<()> {
+    let identity = self.identity().await?;
+    let json = bundle::open(share, &identity)?;
+    let payload: SecretPayload =
+      serde_json::from_str(Padding::strip(&json)).context("Invalid secret format")?;
+    // A file entry only points at chunks in the sender's vault.
+    if let SecretPayload::File { .. } = payload {
+      bail!("File entries cannot be shared");
+    }
+
+    self.add_secret(payload).await
+  }
+
+  // Returns whether the entry was a file that could not be opened, in which
+  // case its chunks are left on the server.
+  pub async fn delete_secret(&self, id: String) -> Result<bool> {
+    let token = self.token.as_ref().context("Not logged in")?;
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+
+    // Chunks are not listed with the other records; the file entry says how
+    // many there are. One that no longer opens can still be deleted.
+    let chunks = match self.api.get_secret(token, &id).await? {
+      Some(sec) if sec.secret_type == FILE_TYPE => match self.open_entry(&sec, keys) {
+        Ok(payload) => match payload.expose() {
+          SecretPayload::File { chunks, .. } => Some(*chunks),
+          _ => Some(0),
+        },
+        Err(_) => None,
+      },
+      _ => Some(0),
+    };
+
+    self.api.delete_secret(token, &id).await?;
+    for index in 0..chunks.unwrap_or(0) {
+      self
+        .api
+        .delete_secret(token, &file_chunk_id(&id, index))
+        .await?;
+    }
+
+    let secrets = self.api.get_secrets(token).await?;
+    let (mut manifest, _) = self.trusted_manifest(&secrets, keys.primary()).await?;
+    manifest.record_delete(&id);
+    manifest.revision += 1;
+
+    self
+      .api
+      .add_secret(
+        token,
+        vec![self.manifest_record(&manifest, keys.primary())?],
+      )
+      .await?;
+    self.save_manifest(&manifest).await?;
+    Ok(chunks.is_none())
   }
 
-  pub async fn delete_secret(&self, id: String) -> Result<()> {
+  // Takes what the server holds now as the vault state, for when sync flagged
+  // a change that is known to be fine, such as a restored server backup.
+  pub async fn accept_vault_state(&self) -> Result<()> {
     let token = self.token.as_ref().context("Not logged in")?;
-    self.api.delete_secret(token, &id).await
+    let keys = self.keys.as_ref().context("Vault is locked")?;
+
+    let secrets = self.api.get_secrets(token).await?;
+    let (mut manifest, _) = self.trusted_manifest(&secrets, keys.primary()).await?;
+
+    for issue in manifest.check(&secrets) {
+      if let IntegrityIssue::Missing(id) | IntegrityIssue::Stale(id) = issue {
+        match secrets.iter().find(|s| s.id == id) {
+          Some(sec) => manifest.record_write(sec),
+          None => manifest.record_delete(&id),
+        }
+      }
+    }
+    manifest.adopt(&secrets);
+    manifest.revision += 1;
+
+    self
+      .api
+      .add_secret(
+        token,
+        vec![self.manifest_record(&manifest, keys.primary())?],
+      )
+      .await?;
+    self.save_manifest(&manifest).await
+  }
+
+  fn manifest_context(&self) -> Vec<u8> {
+    crypto::associated_data(
+      MANIFEST_ID,
+      MANIFEST_TYPE,
+      self.current_user.as_deref().unwrap_or_default(),
+    )
+  }
+
+  async fn load_manifest(&self) -> Result<Option<Manifest>> {
+    let login = self.current_user.as_deref().unwrap_or_default();
+    manifest::load_local(&self.api.manifest_path(login)?).await
+  }
+
+  async fn save_manifest(&self, manifest: &Manifest) -> Result<()> {
+    let login = self.current_user.as_deref().unwrap_or_default();
+    manifest::save_local(&self.api.manifest_path(login)?, manifest).await
+  }
+
+  fn manifest_record(&self, manifest: &Manifest, key: &CipherKey) -> Result<Secret> {
+    manifest.to_record(
+      key,
+      self.current_user.as_deref().unwrap_or_default(),
+      &self.manifest_context(),
+    )
+  }
+
+  // The manifest to build on: the server's copy if it verifies and is not older
+  // than the last one seen here, otherwise the local copy.
+  async fn trusted_manifest(
+    &self,
+    secrets: &[Secret],
+    key: &CipherKey,
+  ) -> Result<(Manifest, Vec<IntegrityIssue>)> {
+    let local = self.load_manifest().await?;
+    let served = secrets
+      .iter()
+      .find(|s| s.id == MANIFEST_ID && s.secret_type == MANIFEST_TYPE)
+      .map(|sec| Manifest::from_record(sec, key, &self.manifest_context()));
+
+    Ok(match (served, local) {
+      (Some(Ok(served)), Some(local)) if served.revision < local.revision => {
+        let issue = IntegrityIssue::ManifestRolledBack {
+          seen: local.revision,
+          served: served.revision,
+        };
+        (local, vec![issue])
+      }
+      (Some(Ok(served)), _) => (served, vec![]),
+      (Some(Err(_)), local) => (
+        local.unwrap_or_default(),
+        vec![IntegrityIssue::ManifestInvalid],
+      ),
+      (None, Some(local)) if local.revision > 0 => {
+        let issue = IntegrityIssue::ManifestRolledBack {
+          seen: local.revision,
+          served: 0,
+        };
+        (local, vec![issue])
+      }
+      (None, local) => (local.unwrap_or_default(), vec![]),
+    })
+  }
+
+  async fn next_manifest(
+    &self,
+    secrets: &[Secret],
+    key: &CipherKey,
+    written: &[Secret],
+  ) -> Result<Manifest> {
+    let (mut manifest, _) = self.trusted_manifest(secrets, key).await?;
+    for sec in written.iter().filter(|s| !s.is_control()) {
+      manifest.record_write(sec);
+    }
+    manifest.revision += 1;
+    Ok(manifest)
+  }
+
+  // Uploads entries in one request with the manifest revision that covers them.
+  async fn publish(&self, token: &str, key: &CipherKey, records: Vec<Secret>) -> Result<()> {
+    let secrets = self.api.get_secrets(token).await?;
+    let manifest = self.next_manifest(&secrets, key, &records).await?;
+
+    let mut upload = records;
+    upload.push(self.manifest_record(&manifest, key)?);
+    self.api.add_secret(token, upload).await?;
+
+    self.save_manifest(&manifest).await
+  }
+}
+
+fn file_chunk_id(file_id: &str, index: u32) -> String {
+  format!("{}/{}", file_id, index)
+}
+
+// Fills as much of `buffer` as the file has left; short reads are retried.
+async fn read_chunk(file: &mut fs::File, buffer: &mut [u8]) -> Result<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    let read = file.read(&mut buffer[filled..]).await?;
+    if read == 0 {
+      break;
+    }
+    filled += read;
+  }
+  Ok(filled)
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default()
+}
+
+// Renders a session lifetime as "2h 05m" or "12m".
+pub fn format_remaining(remaining: Duration) -> String {
+  let minutes = remaining.as_secs() / 60;
+  if minutes >= 60 {
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+  } else {
+    format!("{}m", minutes)
   }
 }
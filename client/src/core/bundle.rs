@@ -0,0 +1,183 @@
+use aes_gcm::aead::OsRng;
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+use crate::core::crypto::{self, CipherKey, KeyRing};
+use crate::core::models::ShareBundle;
+use crate::core::secret::{SecretString, Sensitive};
+
+pub const PUBLIC_KEY_PREFIX: &str = "gk-x25519:";
+pub const BUNDLE_VERSION: u8 = 1;
+
+// An X25519 keypair that others seal share bundles to. Only the public half
+// ever leaves the vault.
+pub struct Identity {
+  secret: StaticSecret,
+}
+
+impl Identity {
+  pub fn generate() -> Self {
+    Self {
+      secret: StaticSecret::random_from_rng(OsRng),
+    }
+  }
+
+  pub fn from_secret(encoded: &str) -> Result<Self> {
+    let mut bytes: [u8; 32] = URL_SAFE_NO_PAD
+      .decode(encoded)
+      .ok()
+      .and_then(|b| b.try_into().ok())
+      .ok_or_else(|| anyhow!("Invalid identity key"))?;
+    let secret = StaticSecret::from(bytes);
+    bytes.zeroize();
+    Ok(Self { secret })
+  }
+
+  pub fn to_secret(&self) -> SecretString {
+    SecretString::new(URL_SAFE_NO_PAD.encode(self.secret.as_bytes()))
+  }
+
+  pub fn public_key(&self) -> String {
+    encode_public_key(&PublicKey::from(&self.secret))
+  }
+}
+
+fn encode_public_key(key: &PublicKey) -> String {
+  format!(
+    "{}{}",
+    PUBLIC_KEY_PREFIX,
+    URL_SAFE_NO_PAD.encode(key.as_bytes())
+  )
+}
+
+pub fn parse_public_key(text: &str) -> Result<PublicKey> {
+  let bytes: [u8; 32] = text
+    .trim()
+    .strip_prefix(PUBLIC_KEY_PREFIX)
+    .and_then(|b| URL_SAFE_NO_PAD.decode(b).ok())
+    .and_then(|b| b.try_into().ok())
+    .ok_or_else(|| anyhow!("Invalid public key, expected {}...", PUBLIC_KEY_PREFIX))?;
+  Ok(PublicKey::from(bytes))
+}
+
+fn bundle_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> CipherKey {
+  let mut salt = ephemeral.as_bytes().to_vec();
+  salt.extend_from_slice(recipient.as_bytes());
+  CipherKey::exchange(shared, &salt)
+}
+
+fn bundle_context(recipient: &str) -> Vec<u8> {
+  crypto::associated_data("share", "bundle", recipient)
+}
+
+// Seals to a fresh ephemeral key, so the bundle says nothing about who made it
+// and only the recipient's identity opens it.
+pub fn seal(plaintext: &str, recipient: &str) -> Result<ShareBundle> {
+  let recipient_key = parse_public_key(recipient)?;
+  let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+  let ephemeral_key = PublicKey::from(&ephemeral);
+
+  let shared = ephemeral.diffie_hellman(&recipient_key);
+  if !shared.was_contributory() {
+    bail!("Invalid public key");
+  }
+
+  let key = bundle_key(shared.as_bytes(), &ephemeral_key, &recipient_key);
+  let recipient = encode_public_key(&recipient_key);
+
+  Ok(ShareBundle {
+    version: BUNDLE_VERSION,
+    data: crypto::encrypt_string(plaintext, &key, &bundle_context(&recipient))?,
+    recipient,
+    ephemeral: encode_public_key(&ephemeral_key),
+  })
+}
+
+pub fn open(bundle: &ShareBundle, identity: &Identity) -> Result<Sensitive<String>> {
+  if bundle.version != BUNDLE_VERSION {
+    bail!("Unsupported share bundle version: {}", bundle.version);
+  }
+  if bundle.recipient != identity.public_key() {
+    bail!("This bundle was sealed for someone else");
+  }
+
+  let ephemeral_key = parse_public_key(&bundle.ephemeral)?;
+  let recipient_key = PublicKey::from(&identity.secret);
+  let shared = identity.secret.diffie_hellman(&ephemeral_key);
+
+  let key = bundle_key(shared.as_bytes(), &ephemeral_key, &recipient_key);
+  crypto::decrypt_string(
+    &bundle.data,
+    &KeyRing::new(key),
+    &bundle_context(&bundle.recipient),
+  )
+  .map(Sensitive::new)
+  .map_err(|_| anyhow!("Share bundle is damaged or was tampered with"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bundles_open_for_their_recipient() {
+    let identity = Identity::generate();
+    let bundle = seal("secret payload", &identity.public_key()).unwrap();
+    assert_eq!(bundle.recipient, identity.public_key());
+    assert_ne!(bundle.ephemeral, identity.public_key());
+    assert_eq!(open(&bundle, &identity).unwrap().expose(), "secret payload");
+
+    // The identity survives being stored as its secret.
+    let restored = Identity::from_secret(&identity.to_secret()).unwrap();
+    assert_eq!(restored.public_key(), identity.public_key());
+    assert_eq!(open(&bundle, &restored).unwrap().expose(), "secret payload");
+  }
+
+  #[test]
+  fn bundles_refuse_anyone_else() {
+    let identity = Identity::generate();
+    let other = Identity::generate();
+    let bundle = seal("secret payload", &identity.public_key()).unwrap();
+
+    let err = open(&bundle, &other).err().unwrap();
+    assert_eq!(err.to_string(), "This bundle was sealed for someone else");
+
+    // Readdressing the bundle does not help either.
+    let readdressed = ShareBundle {
+      version: bundle.version,
+      recipient: other.public_key(),
+      ephemeral: bundle.ephemeral.clone(),
+      data: bundle.data.clone(),
+    };
+    let err = open(&readdressed, &other).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Share bundle is damaged or was tampered with"
+    );
+
+    let unknown = ShareBundle {
+      version: BUNDLE_VERSION + 1,
+      ..bundle
+    };
+    assert!(open(&unknown, &identity).is_err());
+  }
+
+  #[test]
+  fn malformed_public_keys_are_rejected() {
+    let key = Identity::generate().public_key();
+    assert!(parse_public_key(&format!("  {}\n", key)).is_ok());
+    for text in [
+      "",
+      key.trim_start_matches(PUBLIC_KEY_PREFIX),
+      &key[..key.len() - 2],
+      "gk-x25519:not base64!",
+    ] {
+      assert!(parse_public_key(text).is_err(), "{:?} was accepted", text);
+    }
+    // The all-zero point would make every bundle key the same.
+    let zero = format!("{}{}", PUBLIC_KEY_PREFIX, URL_SAFE_NO_PAD.encode([0u8; 32]));
+    assert!(seal("secret payload", &zero).is_err());
+  }
+}
@@ -1,9 +1,36 @@
 use anyhow::{Context, Result, anyhow};
-use reqwest::Client;
+use reqwest::{Client, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::SystemTime;
 use tokio::fs;
-use tokio::io::AsyncReadExt;
 
-use crate::core::models::{AuthRequest, Secret, SyncRequest, Token};
+use crate::core::models::{
+  AuthRequest, ChangePasswordRequest, Claims, FILE_CHUNK_TYPE, RecoverRequest, RecoveryRequest,
+  Secret, SyncRequest, Token,
+};
+
+// The server turned the session token down, which in practice means it
+// expired. Front ends send the user back to the login screen on it.
+#[derive(Debug)]
+pub struct SessionExpired;
+
+impl fmt::Display for SessionExpired {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Session expired, please log in again")
+  }
+}
+
+impl std::error::Error for SessionExpired {}
+
+fn check_session(response: &Response, failure: &str) -> Result<()> {
+  match response.status() {
+    status if status.is_success() => Ok(()),
+    StatusCode::UNAUTHORIZED => Err(SessionExpired.into()),
+    _ => Err(anyhow!("{}", failure)),
+  }
+}
 
 pub struct GopherClient {
   base_url: String,
@@ -18,35 +45,121 @@ impl GopherClient {
     }
   }
 
-  async fn save_token(token: &str) -> Result<()> {
-    let home = home::home_dir().context("Missing HOME directory")?;
-    let token_path = home.join(".goph_token");
-    fs::write(&token_path, token).await?;
-    Ok(())
+  // Tokens live under $XDG_STATE_HOME/gophkeeper/sessions, in a directory per
+  // server with one file per user. Auto login picks the newest one.
+  fn session_dir(&self) -> Result<PathBuf> {
+    Ok(
+      state_dir()?
+        .join("sessions")
+        .join(file_name(&self.base_url)),
+    )
   }
 
-  pub async fn load_token() -> Result<String> {
-    let home = home::home_dir().context("Missing HOME directory")?;
-    let token_path = home.join(".goph_token");
+  // The last vault manifest seen for a user, laid out like the tokens: the
+  // same login on another server is another vault.
+  pub fn manifest_path(&self, login: &str) -> Result<PathBuf> {
+    Ok(
+      state_dir()?
+        .join("manifests")
+        .join(file_name(&self.base_url))
+        .join(format!("{}.json", file_name(login))),
+    )
+  }
 
-    let mut file = fs::File::open(&token_path)
-      .await
-      .context("Token file not found. Please login first.")?;
+  fn token_path(&self, login: &str) -> Result<PathBuf> {
+    Ok(
+      self
+        .session_dir()?
+        .join(format!("{}.token", file_name(login))),
+    )
+  }
 
-    let mut token_content = String::new();
-    file.read_to_string(&mut token_content).await?;
+  async fn save_token(&self, login: &str, token: &str) -> Result<()> {
+    let dir = self.session_dir()?;
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(&dir).await?;
+
+    let path = self.token_path(login)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path).await?;
+    // The mode above only applies to new files; tighten one left over from
+    // before as well.
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      file
+        .set_permissions(std::fs::Permissions::from_mode(0o600))
+        .await?;
+    }
+    tokio::io::AsyncWriteExt::write_all(&mut file, token.as_bytes()).await?;
 
-    Ok(token_content.trim().to_string())
+    // Older clients kept a single token in the home directory.
+    if let Some(legacy) = home::home_dir().map(|home| home.join(".goph_token"))
+      && legacy.exists()
+    {
+      fs::remove_file(legacy).await?;
+    }
+    Ok(())
   }
 
-  pub async fn delete_token() -> Result<()> {
-    let home = home::home_dir().context("Missing HOME directory")?;
-    let token_path = home.join(".goph_token");
+  // Returns the newest token with its claims. The file it came from must be
+  // the one its login would be saved under, so a token copied over another
+  // user's file is not taken for theirs.
+  pub async fn load_token(&self) -> Result<Option<(String, Claims)>> {
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    if let Ok(mut entries) = fs::read_dir(self.session_dir()?).await {
+      while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "token") {
+          continue;
+        }
+        let modified = entry.metadata().await?.modified()?;
+        if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+          newest = Some((modified, path));
+        }
+      }
+    }
+    let Some((_, path)) = newest else {
+      return Ok(None);
+    };
 
-    if token_path.exists() {
-      fs::remove_file(token_path).await?;
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = fs::metadata(&path).await?.permissions().mode();
+      if mode & 0o077 != 0 {
+        return Err(anyhow!(
+          "Token file {} is accessible by other users; delete it and log in again",
+          path.display()
+        ));
+      }
     }
 
+    let token = fs::read_to_string(&path).await?.trim().to_string();
+    let claims = jsonwebtoken::dangerous::insecure_decode::<Claims>(&token)
+      .with_context(|| format!("Token file {} is damaged", path.display()))?
+      .claims;
+    if self.token_path(&claims.login)? != path {
+      return Err(anyhow!(
+        "Token file {} does not belong to user {}; delete it and log in again",
+        path.display(),
+        claims.login
+      ));
+    }
+    Ok(Some((token, claims)))
+  }
+
+  pub async fn delete_token(&self, login: &str) -> Result<()> {
+    let path = self.token_path(login)?;
+    if path.exists() {
+      fs::remove_file(path).await?;
+    }
     Ok(())
   }
 
@@ -83,29 +196,130 @@ impl GopherClient {
     let token_obj: Token = response.json().await?;
     let jwt = token_obj.token;
 
-    Self::save_token(&jwt).await?;
+    self.save_token(login, &jwt).await?;
+
+    Ok(jwt)
+  }
+
+  pub async fn change_password(
+    &self,
+    token: &str,
+    password: &str,
+    new_password: &str,
+  ) -> Result<()> {
+    let payload = ChangePasswordRequest {
+      password: password.to_string(),
+      new_password: new_password.to_string(),
+    };
+    let url = format!("{}/api/password", self.base_url);
+
+    let response = self
+      .client
+      .post(&url)
+      .header("Authorization", format!("Bearer {}", token))
+      .json(&payload)
+      .send()
+      .await?;
+
+    check_session(&response, "Password change failed")?;
+
+    Ok(())
+  }
+
+  pub async fn set_recovery(&self, token: &str, recovery: &str) -> Result<()> {
+    let payload = RecoveryRequest {
+      recovery: recovery.to_string(),
+    };
+    let url = format!("{}/api/recovery", self.base_url);
+
+    let response = self
+      .client
+      .post(&url)
+      .header("Authorization", format!("Bearer {}", token))
+      .json(&payload)
+      .send()
+      .await?;
+
+    check_session(&response, "Failed to register recovery key")?;
+
+    Ok(())
+  }
+
+  pub async fn clear_recovery(&self, token: &str) -> Result<()> {
+    let url = format!("{}/api/recovery", self.base_url);
+
+    let response = self
+      .client
+      .delete(&url)
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+
+    check_session(&response, "Failed to revoke recovery key")?;
+
+    Ok(())
+  }
+
+  pub async fn recover(&self, login: &str, recovery: &str, new_password: &str) -> Result<String> {
+    let payload = RecoverRequest {
+      login: login.to_string(),
+      recovery: recovery.to_string(),
+      new_password: new_password.to_string(),
+    };
+    let url = format!("{}/api/recover", self.base_url);
+
+    let response = self.client.post(&url).json(&payload).send().await?;
+
+    if !response.status().is_success() {
+      return Err(anyhow!("Recovery key was not accepted"));
+    }
+
+    let token_obj: Token = response.json().await?;
+    let jwt = token_obj.token;
+
+    self.save_token(login, &jwt).await?;
 
     Ok(jwt)
   }
 
+  // Every record but file chunks, which are only ever read one at a time
+  // through `get_secret`.
   pub async fn get_secrets(&self, token: &str) -> Result<Vec<Secret>> {
     let url = format!("{}/api/data", self.base_url);
 
     let response = self
       .client
       .get(&url)
+      .query(&[("skip_type", FILE_CHUNK_TYPE)])
       .header("Authorization", format!("Bearer {}", token))
       .send()
       .await?;
 
-    if !response.status().is_success() {
-      return Err(anyhow!("Failed to fetch secrets"));
-    }
+    check_session(&response, "Failed to fetch secrets")?;
 
     let secrets: Vec<Secret> = response.json().await?;
     Ok(secrets)
   }
 
+  pub async fn get_secret(&self, token: &str, id: &str) -> Result<Option<Secret>> {
+    let url = format!("{}/api/data", self.base_url);
+
+    let response = self
+      .client
+      .get(&url)
+      .query(&[("id", id)])
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+    check_session(&response, "Failed to fetch secret")?;
+
+    Ok(Some(response.json().await?))
+  }
+
   pub async fn add_secret(&self, token: &str, secrets: Vec<Secret>) -> Result<()> {
     let url = format!("{}/api/data", self.base_url);
     let payload = SyncRequest { secrets };
@@ -118,9 +332,7 @@ impl GopherClient {
       .send()
       .await?;
 
-    if !response.status().is_success() {
-      return Err(anyhow!("Sync failed"));
-    }
+    check_session(&response, "Sync failed")?;
 
     Ok(())
   }
@@ -135,10 +347,41 @@ impl GopherClient {
       .send()
       .await?;
 
-    if !response.status().is_success() {
-      return Err(anyhow!("Delete failed"));
-    }
+    check_session(&response, "Delete failed")?;
 
     Ok(())
   }
 }
+
+fn state_dir() -> Result<PathBuf> {
+  let state = match std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+    Some(dir) => PathBuf::from(dir),
+    None => home::home_dir()
+      .context("Missing HOME directory")?
+      .join(".local/state"),
+  };
+  Ok(state.join("gophkeeper"))
+}
+
+// Server addresses and logins as file names. Hashed rather than escaped, so no
+// two of them can end up sharing a file, whatever characters they hold.
+fn file_name(text: &str) -> String {
+  format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn distinct_names_never_share_a_file() {
+    let names = ["alice.b", "alice_b", "alice/b", "Alice.b", "../alice"];
+    let files: std::collections::HashSet<String> = names.iter().map(|n| file_name(n)).collect();
+    assert_eq!(files.len(), names.len());
+    assert!(
+      files
+        .iter()
+        .all(|f| f.len() == 64 && f.bytes().all(|b| b.is_ascii_hexdigit()))
+    );
+  }
+}
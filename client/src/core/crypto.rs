@@ -1,66 +1,1134 @@
-use aes_gcm::aead::OsRng;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::generic_array::typenum::Unsigned;
 use aes_gcm::aead::rand_core::RngCore;
-use aes_gcm::{Aes256Gcm, Key, KeyInit, aead::Aead};
-use anyhow::Result;
-use base64::{Engine as _, engine::general_purpose::STANDARD};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload};
+use anyhow::{Result, anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{
+  Engine as _,
+  engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+};
+use bip39::Language;
+use chacha20poly1305::XChaCha20Poly1305;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::fmt::Write;
+use std::fmt;
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+use crate::core::hardening;
+use crate::core::keyfile::Keyfile;
+use crate::core::secret::{SecretString, Sensitive};
 
 pub const NONCE_SIZE: usize = 12;
+pub const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+
+pub const ENVELOPE_PREFIX: &str = "$gk$";
+// Version 1 envelopes authenticate only the header; version 2 also binds the
+// caller-supplied context (secret id, type and owner) as associated data.
+pub const ENVELOPE_VERSION: u8 = 2;
+pub const ENVELOPE_MIN_VERSION: u8 = 1;
+
+pub type VaultKey = [u8; KEY_SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+  pub m_cost: u32,
+  pub t_cost: u32,
+  pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+  fn default() -> Self {
+    Self {
+      m_cost: 19 * 1024,
+      t_cost: 2,
+      p_cost: 1,
+    }
+  }
+}
+
+// Entry plaintexts are padded up to the next bucket so that ciphertext sizes
+// only reveal a size class. Past the largest bucket they are rounded up to a
+// multiple of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Padding {
+  buckets: Vec<usize>,
+}
+
+impl Default for Padding {
+  fn default() -> Self {
+    Self {
+      buckets: vec![128, 256, 512, 1024, 2048, 4096],
+    }
+  }
+}
+
+impl Padding {
+  pub fn new(mut buckets: Vec<usize>) -> Result<Self> {
+    if buckets.contains(&0) {
+      bail!("Padding buckets must be positive");
+    }
+    buckets.sort_unstable();
+    buckets.dedup();
+    Ok(Self { buckets })
+  }
+
+  pub fn padded_len(&self, len: usize) -> usize {
+    let Some(&largest) = self.buckets.last() else {
+      return len;
+    };
+
+    match self.buckets.iter().find(|&&b| b >= len) {
+      Some(&bucket) => bucket,
+      None => len.div_ceil(largest) * largest,
+    }
+  }
+
+  // Pads serialized JSON with trailing spaces, which any JSON parser ignores,
+  // so entries written before padding existed read the same way.
+  pub fn apply(&self, json: &str) -> String {
+    let json = Self::strip(json);
+    let target = self.padded_len(json.len());
+
+    let mut padded = String::with_capacity(target);
+    padded.push_str(json);
+    padded.extend(std::iter::repeat_n(' ', target - json.len()));
+    padded
+  }
+
+  pub fn strip(json: &str) -> &str {
+    json.trim_end_matches(' ')
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cipher {
+  #[default]
+  #[serde(rename = "aes-256-gcm")]
+  Aes256Gcm,
+  // 192-bit random nonces, so a long-lived vault key never gets near a nonce
+  // collision, and fast without AES hardware.
+  #[serde(rename = "xchacha20-poly1305")]
+  XChaCha20Poly1305,
+}
+
+impl Cipher {
+  pub fn id(&self) -> &'static str {
+    match self {
+      Cipher::Aes256Gcm => "aes-256-gcm",
+      Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Result<Self> {
+    match id {
+      "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+      "xchacha20-poly1305" => Ok(Cipher::XChaCha20Poly1305),
+      other => Err(anyhow!("Unsupported cipher: {}", other)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kdf {
+  Argon2id { params: KdfParams, salt: Vec<u8> },
+  // Random vault key, unwrapped from the vault header rather than derived.
+  Wrapped,
+  // Key derived from a high-entropy recovery key; no stretching needed.
+  Recovery,
+  // One-off key agreed by X25519 between a share bundle and its recipient.
+  Exchange,
+}
+
+impl Kdf {
+  fn encode(&self) -> String {
+    match self {
+      Kdf::Argon2id { params, salt } => format!(
+        "argon2id,m={},t={},p={},s={}",
+        params.m_cost,
+        params.t_cost,
+        params.p_cost,
+        STANDARD_NO_PAD.encode(salt)
+      ),
+      Kdf::Wrapped => "wrapped".into(),
+      Kdf::Recovery => "recovery".into(),
+      Kdf::Exchange => "x25519".into(),
+    }
+  }
+
+  fn decode(segment: &str) -> Result<Self> {
+    let mut parts = segment.split(',');
+
+    match parts.next() {
+      Some("argon2id") => {
+        let mut params = KdfParams::default();
+        let mut salt = None;
+
+        for part in parts {
+          let (name, value) = part
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Malformed KDF parameter: {}", part))?;
+
+          match name {
+            "m" => params.m_cost = value.parse()?,
+            "t" => params.t_cost = value.parse()?,
+            "p" => params.p_cost = value.parse()?,
+            "s" => salt = Some(STANDARD_NO_PAD.decode(value)?),
+            other => bail!("Unknown KDF parameter: {}", other),
+          }
+        }
+
+        Ok(Kdf::Argon2id {
+          params,
+          salt: salt.ok_or_else(|| anyhow!("Missing KDF salt"))?,
+        })
+      }
+      Some("wrapped") => Ok(Kdf::Wrapped),
+      Some("recovery") => Ok(Kdf::Recovery),
+      Some("x25519") => Ok(Kdf::Exchange),
+      Some(other) => Err(anyhow!("Unsupported KDF: {}", other)),
+      None => Err(anyhow!("Missing KDF")),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+  pub version: u8,
+  pub cipher: Cipher,
+  pub kdf: Kdf,
+}
+
+impl Header {
+  pub fn encode(&self) -> String {
+    format!(
+      "{}v={}$c={}$k={}",
+      ENVELOPE_PREFIX,
+      self.version,
+      self.cipher.id(),
+      self.kdf.encode()
+    )
+  }
+
+  pub fn decode(header: &str) -> Result<Self> {
+    let body = header
+      .strip_prefix(ENVELOPE_PREFIX)
+      .ok_or_else(|| anyhow!("Missing envelope prefix"))?;
+
+    let mut version = None;
+    let mut cipher = None;
+    let mut kdf = None;
+
+    for segment in body.split('$') {
+      let (name, value) = segment
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Malformed envelope segment: {}", segment))?;
+
+      match name {
+        "v" => version = Some(value.parse::<u8>()?),
+        "c" => cipher = Some(Cipher::from_id(value)?),
+        "k" => kdf = Some(Kdf::decode(value)?),
+        other => bail!("Unknown envelope segment: {}", other),
+      }
+    }
+
+    let version = version.ok_or_else(|| anyhow!("Missing envelope version"))?;
+    if !(ENVELOPE_MIN_VERSION..=ENVELOPE_VERSION).contains(&version) {
+      bail!("Unsupported envelope version: {}", version);
+    }
+
+    Ok(Self {
+      version,
+      cipher: cipher.ok_or_else(|| anyhow!("Missing envelope cipher"))?,
+      kdf: kdf.ok_or_else(|| anyhow!("Missing envelope KDF"))?,
+    })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+  Legacy,
+  Envelope(Header),
+}
+
+#[derive(Clone)]
+pub struct CipherKey {
+  key: VaultKey,
+  kdf: Kdf,
+  cipher: Cipher,
+}
+
+impl CipherKey {
+  pub fn derive(
+    password: &str,
+    salt: &[u8],
+    params: &KdfParams,
+    keyfile: Option<&Keyfile>,
+  ) -> Result<Self> {
+    Ok(Self {
+      key: derive_key(password, salt, params, keyfile.map(Keyfile::secret))?,
+      kdf: Kdf::Argon2id {
+        params: *params,
+        salt: salt.to_vec(),
+      },
+      cipher: Cipher::default(),
+    })
+  }
+
+  pub fn generate() -> Self {
+    let mut key = [0u8; KEY_SIZE];
+    OsRng.fill_bytes(&mut key);
+    Self {
+      key,
+      kdf: Kdf::Wrapped,
+      cipher: Cipher::default(),
+    }
+  }
 
-pub fn encrypt_string(data: &str, password: &str) -> Result<String> {
+  pub fn recovery(secret: &[u8]) -> Self {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gophkeeper-recovery-key:");
+    hasher.update(secret);
+    Self {
+      key: hasher.finalize().into(),
+      kdf: Kdf::Recovery,
+      cipher: Cipher::default(),
+    }
+  }
+
+  // Expands an X25519 shared secret; `salt` binds both public keys.
+  pub fn exchange(shared: &[u8], salt: &[u8]) -> Self {
+    let mut key = [0u8; KEY_SIZE];
+    Hkdf::<Sha256>::new(Some(salt), shared)
+      .expand(b"gophkeeper-share-bundle", &mut key)
+      .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Self {
+      key,
+      kdf: Kdf::Exchange,
+      cipher: Cipher::default(),
+    }
+  }
+
+  // HMAC-SHA256 under a subkey of this key, for records that are kept in the
+  // clear but must not be altered.
+  pub fn authenticate(&self, context: &[u8], data: &[u8]) -> String {
+    STANDARD.encode(self.mac(context, data).finalize().into_bytes())
+  }
+
+  pub fn verify(&self, context: &[u8], data: &[u8], tag: &str) -> Result<()> {
+    let tag = STANDARD
+      .decode(tag)
+      .map_err(|e| anyhow!("Invalid base64: {}", e))?;
+    self
+      .mac(context, data)
+      .verify_slice(&tag)
+      .map_err(|_| anyhow!("Authentication failed"))
+  }
+
+  fn mac(&self, context: &[u8], data: &[u8]) -> Hmac<Sha256> {
+    let mut subkey = [0u8; KEY_SIZE];
+    Hkdf::<Sha256>::new(None, &self.key)
+      .expand(b"gophkeeper-mac", &mut subkey)
+      .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    let mut mac =
+      <Hmac<Sha256> as Mac>::new_from_slice(&subkey).expect("HMAC accepts keys of any size");
+    subkey.zeroize();
+    mac.update(context);
+    mac.update(data);
+    mac
+  }
+
+  pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+    self.cipher = cipher;
+    self
+  }
+
+  pub fn cipher(&self) -> Cipher {
+    self.cipher
+  }
+
+  // Raw form of a random key that travels inside the entry it protects, such
+  // as the key of a file's chunks.
+  pub fn export(&self) -> SecretString {
+    SecretString::new(STANDARD.encode(self.key))
+  }
+
+  pub fn import(encoded: &str) -> Result<Self> {
+    let mut bytes = STANDARD
+      .decode(encoded)
+      .map_err(|e| anyhow!("Invalid base64: {}", e))?;
+    let key: Result<VaultKey> = bytes
+      .as_slice()
+      .try_into()
+      .map_err(|_| anyhow!("Key has invalid length"));
+    bytes.zeroize();
+    Ok(Self {
+      key: key?,
+      kdf: Kdf::Wrapped,
+      cipher: Cipher::default(),
+    })
+  }
+
+  fn header(&self) -> Header {
+    Header {
+      version: ENVELOPE_VERSION,
+      cipher: self.cipher,
+      kdf: self.kdf.clone(),
+    }
+  }
+}
+
+impl Drop for CipherKey {
+  fn drop(&mut self) {
+    self.key.zeroize();
+  }
+}
+
+// Keys able to open entries of one vault. New data is always sealed with the
+// primary key; the others only read entries written before an upgrade.
+// The keys sit on the heap, in pages locked against swapping, since a ring
+// lives as long as the vault stays unlocked.
+pub struct KeyRing {
+  keys: Vec<CipherKey>,
+  legacy: Option<Box<VaultKey>>,
+}
+
+// A ring holds the data key and at most one master key.
+const KEYRING_CAPACITY: usize = 2;
+
+impl KeyRing {
+  pub fn new(primary: CipherKey) -> Self {
+    let mut keys = Vec::with_capacity(KEYRING_CAPACITY);
+    hardening::lock(keys.spare_capacity_mut());
+    keys.push(primary);
+    Self { keys, legacy: None }
+  }
+
+  pub fn with_key(mut self, key: CipherKey) -> Self {
+    self.keys.push(key);
+    hardening::lock(&self.keys);
+    self
+  }
+
+  pub fn with_legacy(mut self, legacy: VaultKey) -> Self {
+    let boxed = Box::new(legacy);
+    hardening::lock(std::slice::from_ref(&*boxed));
+    self.legacy = Some(boxed);
+    self
+  }
+
+  pub fn primary(&self) -> &CipherKey {
+    &self.keys[0]
+  }
+
+  pub fn needs_upgrade(&self, encrypted_data: &str) -> Result<bool> {
+    Ok(match inspect(encrypted_data)? {
+      Format::Legacy => true,
+      Format::Envelope(h) => {
+        h.version != ENVELOPE_VERSION
+          || h.kdf != self.primary().kdf
+          || h.cipher != self.primary().cipher
+      }
+    })
+  }
+
+  fn key_for(&self, header: &Header) -> Result<&VaultKey> {
+    self
+      .keys
+      .iter()
+      .find(|k| k.kdf == header.kdf)
+      .map(|k| &k.key)
+      .ok_or_else(|| anyhow!("Entry was encrypted with different key parameters"))
+  }
+}
+
+impl Drop for KeyRing {
+  fn drop(&mut self) {
+    if let Some(legacy) = &mut self.legacy {
+      legacy.zeroize();
+    }
+  }
+}
+
+pub fn generate_salt() -> Vec<u8> {
+  let mut salt = vec![0u8; SALT_SIZE];
+  OsRng.fill_bytes(&mut salt);
+  salt
+}
+
+// A keyfile goes in as the Argon2 secret input, so both factors pass through
+// the same stretching. Without one the output is unchanged.
+pub fn derive_key(
+  password: &str,
+  salt: &[u8],
+  params: &KdfParams,
+  secret: Option<&[u8]>,
+) -> Result<VaultKey> {
+  let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(KEY_SIZE))
+    .map_err(|e| anyhow!("Invalid KDF parameters: {}", e))?;
+
+  let argon2 = match secret {
+    Some(secret) => Argon2::new_with_secret(secret, Algorithm::Argon2id, Version::V0x13, params)
+      .map_err(|e| anyhow!("Invalid keyfile: {}", e))?,
+    None => Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+  };
+
+  let mut key = [0u8; KEY_SIZE];
+  argon2
+    .hash_password_into(password.as_bytes(), salt, &mut key)
+    .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+
+  Ok(key)
+}
+
+// The value sent to the server as the account password. It uses a salt fixed
+// by the login, since it has to be computed before anything can be fetched, and
+// never coincides with the vault key, which is salted with the random per-user
+// salt from the vault header.
+pub fn derive_auth_hash(login: &str, password: &str) -> Result<String> {
+  let mut hasher = Sha256::new();
+  hasher.update(b"gophkeeper-auth:");
+  hasher.update(login.as_bytes());
+  let salt = hasher.finalize();
+
+  let mut key = derive_key(password, &salt, &KdfParams::default(), None)?;
+  let hash = STANDARD.encode(key);
+  key.zeroize();
+  Ok(hash)
+}
+
+// Vaults written before the switch to Argon2id used a single unsalted SHA-256
+// of the password as the AES key. Only used to read such entries.
+pub fn legacy_key(password: &str) -> VaultKey {
   let mut hasher = Sha256::new();
   hasher.update(password.as_bytes());
-  let key = hasher.finalize();
+  hasher.finalize().into()
+}
+
+pub fn inspect(encrypted_data: &str) -> Result<Format> {
+  if !encrypted_data.starts_with(ENVELOPE_PREFIX) {
+    return Ok(Format::Legacy);
+  }
+
+  let (header, _) = split_envelope(encrypted_data)?;
+  Ok(Format::Envelope(Header::decode(header)?))
+}
+
+fn split_envelope(encrypted_data: &str) -> Result<(&str, &str)> {
+  encrypted_data
+    .rsplit_once('$')
+    .ok_or_else(|| anyhow!("Malformed envelope"))
+}
+
+pub fn associated_data(id: &str, secret_type: &str, user_login: &str) -> Vec<u8> {
+  let mut aad = Vec::new();
+  for part in [id, secret_type, user_login] {
+    aad.extend_from_slice(&(part.len() as u32).to_be_bytes());
+    aad.extend_from_slice(part.as_bytes());
+  }
+  aad
+}
+
+pub fn encrypt_string(data: &str, key: &CipherKey, context: &[u8]) -> Result<String> {
+  encrypt_bytes(data.as_bytes(), key, context)
+}
+
+pub fn decrypt_string(encrypted_data: &str, keys: &KeyRing, context: &[u8]) -> Result<String> {
+  let plaintext = decrypt_bytes(encrypted_data, keys, context)?;
+  String::from_utf8(plaintext).map_err(|e| anyhow!("Invalid UTF-8: {}", e))
+}
+
+pub fn wrap_key(data_key: &CipherKey, key: &CipherKey, context: &[u8]) -> Result<String> {
+  encrypt_bytes(&data_key.key, key, context)
+}
+
+pub fn unwrap_key(wrapped: &str, key: &CipherKey, context: &[u8]) -> Result<CipherKey> {
+  let Format::Envelope(header) = inspect(wrapped)? else {
+    bail!("Wrapped key is not an envelope");
+  };
+
+  if header.kdf != key.kdf {
+    bail!("Wrapped key was sealed with different key parameters");
+  }
+
+  let bytes = open_envelope(wrapped, &header, &key.key, context)?;
+  let key: VaultKey = bytes
+    .try_into()
+    .map_err(|_| anyhow!("Wrapped key has invalid length"))?;
+
+  Ok(CipherKey {
+    key,
+    kdf: Kdf::Wrapped,
+    cipher: Cipher::default(),
+  })
+}
+
+pub fn encrypt_bytes(data: &[u8], key: &CipherKey, context: &[u8]) -> Result<String> {
+  let header = key.header().encode();
+  let mut aad = header.as_bytes().to_vec();
+  aad.extend_from_slice(context);
+
+  let sealed = seal(key.cipher, &key.key, data, &aad)?;
+
+  Ok(format!("{}${}", header, STANDARD.encode(sealed)))
+}
+
+pub fn decrypt_bytes(encrypted_data: &str, keys: &KeyRing, context: &[u8]) -> Result<Vec<u8>> {
+  Ok(match inspect(encrypted_data)? {
+    Format::Legacy => {
+      let key = keys
+        .legacy
+        .as_ref()
+        .ok_or_else(|| anyhow!("Legacy entry cannot be read without the legacy key"))?;
+      let combined = STANDARD
+        .decode(encrypted_data)
+        .map_err(|e| anyhow!("Invalid base64: {}", e))?;
+      open(Cipher::Aes256Gcm, key, &combined, &[])?
+    }
+    Format::Envelope(header) => {
+      open_envelope(encrypted_data, &header, keys.key_for(&header)?, context)?
+    }
+  })
+}
+
+// The envelope parsed, but its tag did not verify under the key its header
+// named. That is tampering only if the key is known to be the right one; a
+// wrong key looks just the same.
+#[derive(Debug)]
+pub struct AuthenticationFailed;
+
+impl fmt::Display for AuthenticationFailed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Decryption failed: wrong key, or the entry was altered, swapped or relabelled")
+  }
+}
+
+impl std::error::Error for AuthenticationFailed {}
+
+fn open_envelope(
+  encrypted_data: &str,
+  header: &Header,
+  key: &VaultKey,
+  context: &[u8],
+) -> Result<Vec<u8>> {
+  let (header_text, payload) = split_envelope(encrypted_data)?;
+  let combined = STANDARD
+    .decode(payload)
+    .map_err(|e| anyhow!("Invalid base64: {}", e))?;
+
+  let mut aad = header_text.as_bytes().to_vec();
+  if header.version >= 2 {
+    aad.extend_from_slice(context);
+  }
 
-  let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+  open(header.cipher, key, &combined, &aad).map_err(|e| {
+    if header.version >= 2 {
+      AuthenticationFailed.into()
+    } else {
+      e
+    }
+  })
+}
+
+fn seal(cipher: Cipher, key: &VaultKey, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+  match cipher {
+    Cipher::Aes256Gcm => seal_with::<Aes256Gcm>(key, plaintext, aad),
+    Cipher::XChaCha20Poly1305 => seal_with::<XChaCha20Poly1305>(key, plaintext, aad),
+  }
+}
 
-  let mut nonce_bytes = [0u8; NONCE_SIZE];
-  OsRng.fill_bytes(&mut nonce_bytes);
+fn open(cipher: Cipher, key: &VaultKey, combined: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+  match cipher {
+    Cipher::Aes256Gcm => open_with::<Aes256Gcm>(key, combined, aad),
+    Cipher::XChaCha20Poly1305 => open_with::<XChaCha20Poly1305>(key, combined, aad),
+  }
+}
 
-  let nonce = aes_gcm::Nonce::from_slice(&nonce_bytes);
+fn seal_with<C: Aead + KeyInit>(key: &VaultKey, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+  let cipher = C::new_from_slice(key).map_err(|e| anyhow!("Invalid key: {}", e))?;
+  let nonce = C::generate_nonce(&mut OsRng);
 
   let ciphertext = cipher
-    .encrypt(nonce, data.as_bytes())
-    .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+    .encrypt(
+      &nonce,
+      Payload {
+        msg: plaintext,
+        aad,
+      },
+    )
+    .map_err(|e| anyhow!("Encryption failed: {}", e))?;
 
-  let mut combined = nonce_bytes.to_vec();
+  let mut combined = nonce.to_vec();
   combined.extend_from_slice(&ciphertext);
 
-  Ok(STANDARD.encode(combined))
+  Ok(combined)
 }
 
-pub fn decrypt_string(encrypted_data: &str, password: &str) -> Result<String> {
-  let combined = STANDARD
-    .decode(encrypted_data)
-    .map_err(|e| anyhow::anyhow!("Invalid base64: {}", e))?;
+fn open_with<C: Aead + KeyInit>(key: &VaultKey, combined: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+  let nonce_size = <C as AeadCore>::NonceSize::USIZE;
+  if combined.len() < nonce_size {
+    return Err(anyhow!("Ciphertext too short"));
+  }
+
+  let (nonce_bytes, ciphertext) = combined.split_at(nonce_size);
+  let nonce = Nonce::<C>::from_slice(nonce_bytes);
+
+  let cipher = C::new_from_slice(key).map_err(|e| anyhow!("Invalid key: {}", e))?;
+  cipher
+    .decrypt(
+      nonce,
+      Payload {
+        msg: ciphertext,
+        aad,
+      },
+    )
+    .map_err(|e| anyhow!("Decryption failed: {}", e))
+}
+
+// One Shamir share of a secret, split byte by byte over GF(256). Any
+// `threshold` shares of the same split rebuild the secret; fewer reveal
+// nothing about it.
+pub struct Share {
+  pub threshold: u8,
+  pub index: u8,
+  value: Vec<u8>,
+}
+
+const SHARE_CHECKSUM_SIZE: usize = 2;
+
+impl Share {
+  // Threshold, index, value length, value and a truncated SHA-256 checksum,
+  // written as 11-bit words from the BIP39 English list.
+  pub fn to_words(&self) -> SecretString {
+    let mut bytes = vec![self.threshold, self.index, self.value.len() as u8];
+    bytes.extend_from_slice(&self.value);
+    let checksum = Sha256::digest(&bytes);
+    bytes.extend_from_slice(&checksum[..SHARE_CHECKSUM_SIZE]);
+
+    let list = Language::English.word_list();
+    let mut words = Vec::new();
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for byte in &bytes {
+      acc = (acc << 8) | u32::from(*byte);
+      bits += 8;
+      while bits >= 11 {
+        bits -= 11;
+        words.push(list[((acc >> bits) & 0x7ff) as usize]);
+      }
+    }
+    if bits > 0 {
+      words.push(list[((acc << (11 - bits)) & 0x7ff) as usize]);
+    }
 
-  if combined.len() < NONCE_SIZE {
-    return Err(anyhow::anyhow!("Ciphertext too short"));
+    bytes.zeroize();
+    SecretString::new(words.join(" "))
   }
 
-  let (nonce_bytes, ciphertext) = combined.split_at(NONCE_SIZE);
-  let nonce = aes_gcm::Nonce::from_slice(nonce_bytes);
+  pub fn from_words(words: &str) -> Result<Self> {
+    let mut bytes = Vec::new();
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for word in words.split_whitespace() {
+      let index = Language::English
+        .find_word(&word.to_lowercase())
+        .ok_or_else(|| anyhow!("Unknown word in share: {}", word))?;
+      acc = (acc << 11) | u32::from(index);
+      bits += 11;
+      while bits >= 8 {
+        bits -= 8;
+        bytes.push((acc >> bits) as u8);
+      }
+    }
 
-  let mut hasher = Sha256::new();
-  hasher.update(password.as_bytes());
-  let key = hasher.finalize();
+    // The last word may carry up to ten bits of zero padding, which can add a
+    // whole zero byte; the length prefix says where the share really ends.
+    let len = bytes
+      .get(2)
+      .map(|&l| 3 + usize::from(l) + SHARE_CHECKSUM_SIZE);
+    let Some(len) = len.filter(|&l| l <= bytes.len() && bytes.len() - l <= 1) else {
+      bytes.zeroize();
+      bail!("Share is incomplete; check the words");
+    };
+    if acc & ((1 << bits) - 1) != 0 || bytes[len..].iter().any(|&b| b != 0) {
+      bytes.zeroize();
+      bail!("Share is malformed");
+    }
+
+    let (body, checksum) = bytes[..len].split_at(len - SHARE_CHECKSUM_SIZE);
+    if Sha256::digest(body)[..SHARE_CHECKSUM_SIZE] != *checksum {
+      bytes.zeroize();
+      bail!("Share checksum does not match; check the words");
+    }
+
+    let share = Self {
+      threshold: body[0],
+      index: body[1],
+      value: body[3..].to_vec(),
+    };
+    bytes.zeroize();
 
-  let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
-  let plaintext = cipher
-    .decrypt(nonce, ciphertext)
-    .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))?;
+    if share.index == 0 || share.threshold == 0 {
+      bail!("Share is malformed");
+    }
+    Ok(share)
+  }
+}
 
-  String::from_utf8(plaintext).map_err(|e| anyhow::anyhow!("Invalid UTF-8: {}", e))
+impl Drop for Share {
+  fn drop(&mut self) {
+    self.value.zeroize();
+  }
 }
 
-pub fn generate_id(data: &str) -> String {
-  let mut hasher = Sha256::new();
-  hasher.update(data.as_bytes());
-  let result = hasher.finalize();
-  let mut hex = String::new();
-  for byte in result.iter().take(16) {
-    write!(&mut hex, "{:02x}", byte).unwrap();
+pub fn split_secret(secret: &[u8], shares: u8, threshold: u8) -> Result<Vec<Share>> {
+  if threshold < 2 {
+    bail!("Threshold must be at least 2");
+  }
+  if shares < threshold {
+    bail!("Cannot require {} of only {} shares", threshold, shares);
+  }
+  if secret.len() > usize::from(u8::MAX) {
+    bail!("Secret is too long to split");
+  }
+
+  let mut coefficients = vec![0u8; usize::from(threshold - 1)];
+  let mut result: Vec<Share> = (1..=shares)
+    .map(|index| Share {
+      threshold,
+      index,
+      value: Vec::with_capacity(secret.len()),
+    })
+    .collect();
+
+  for &byte in secret {
+    OsRng.fill_bytes(&mut coefficients);
+    for share in &mut result {
+      // Horner's rule for byte + c1*x + ... + c(k-1)*x^(k-1).
+      let mut y = 0u8;
+      for &c in coefficients.iter().rev() {
+        y = gf_mul(y, share.index) ^ c;
+      }
+      share.value.push(gf_mul(y, share.index) ^ byte);
+    }
+  }
+
+  coefficients.zeroize();
+  Ok(result)
+}
+
+pub fn combine_shares(shares: &[Share]) -> Result<Sensitive<Vec<u8>>> {
+  let first = shares.first().ok_or_else(|| anyhow!("No shares given"))?;
+  let threshold = usize::from(first.threshold);
+
+  if shares
+    .iter()
+    .any(|s| s.threshold != first.threshold || s.value.len() != first.value.len())
+  {
+    bail!("Shares come from different splits");
+  }
+
+  let mut indices: Vec<u8> = shares.iter().map(|s| s.index).collect();
+  indices.sort_unstable();
+  indices.dedup();
+  if indices.len() != shares.len() {
+    bail!("The same share was given twice");
+  }
+  if shares.len() < threshold {
+    bail!("{} of {} required shares given", shares.len(), threshold);
+  }
+
+  let used = &shares[..threshold];
+  let mut secret = vec![0u8; first.value.len()];
+
+  // Lagrange interpolation at x = 0; subtraction in GF(256) is XOR.
+  for (j, share) in used.iter().enumerate() {
+    let mut basis = 1u8;
+    for (m, other) in used.iter().enumerate() {
+      if m != j {
+        basis = gf_mul(basis, gf_div(other.index, other.index ^ share.index));
+      }
+    }
+    for (out, &y) in secret.iter_mut().zip(&share.value) {
+      *out ^= gf_mul(y, basis);
+    }
+  }
+
+  Ok(Sensitive::new(secret))
+}
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+  let mut product = 0u8;
+  while b != 0 {
+    if b & 1 != 0 {
+      product ^= a;
+    }
+    let carry = a & 0x80;
+    a <<= 1;
+    if carry != 0 {
+      a ^= 0x1b;
+    }
+    b >>= 1;
+  }
+  product
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+  // b^254 is the inverse of b in GF(256).
+  let mut inverse = 1u8;
+  for _ in 0..254 {
+    inverse = gf_mul(inverse, b);
+  }
+  gf_mul(a, inverse)
+}
+
+// Entry ids are random so that they say nothing about the content and stay the
+// same across edits and re-encryption. Ids of entries written by older clients
+// were a hash of the plaintext; they are only ever compared, so both coexist.
+pub fn generate_id() -> String {
+  Uuid::new_v4().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_PARAMS: KdfParams = KdfParams {
+    m_cost: 64,
+    t_cost: 1,
+    p_cost: 1,
+  };
+
+  #[test]
+  fn headers_round_trip() {
+    let kdfs = [
+      Kdf::Argon2id {
+        params: KdfParams {
+          m_cost: 65536,
+          t_cost: 3,
+          p_cost: 4,
+        },
+        salt: vec![1, 2, 3, 250, 251],
+      },
+      Kdf::Wrapped,
+      Kdf::Recovery,
+      Kdf::Exchange,
+    ];
+    for kdf in kdfs {
+      for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+        let header = Header {
+          version: ENVELOPE_VERSION,
+          cipher,
+          kdf: kdf.clone(),
+        };
+        assert_eq!(Header::decode(&header.encode()).unwrap(), header);
+      }
+    }
+  }
+
+  #[test]
+  fn malformed_headers_are_rejected() {
+    for header in [
+      "gk$v=2$c=aes-256-gcm$k=wrapped",
+      "$gk$v=3$c=aes-256-gcm$k=wrapped",
+      "$gk$v=0$c=aes-256-gcm$k=wrapped",
+      "$gk$c=aes-256-gcm$k=wrapped",
+      "$gk$v=2$k=wrapped",
+      "$gk$v=2$c=aes-256-gcm",
+      "$gk$v=2$c=rot13$k=wrapped",
+      "$gk$v=2$c=aes-256-gcm$k=scrypt",
+      "$gk$v=2$c=aes-256-gcm$k=argon2id,m=1,t=1,p=1",
+      "$gk$v=2$c=aes-256-gcm$k=argon2id,m=1,q=1,s=AAAA",
+      "$gk$v=2$c=aes-256-gcm$k=wrapped$x=1",
+      "$gk$v=2$c=aes-256-gcm$wrapped",
+    ] {
+      assert!(Header::decode(header).is_err(), "{} was accepted", header);
+    }
+  }
+
+  #[test]
+  fn inspect_tells_legacy_from_envelopes() {
+    assert_eq!(inspect("bm90IGFuIGVudmVsb3Bl").unwrap(), Format::Legacy);
+
+    let key = CipherKey::generate().with_cipher(Cipher::XChaCha20Poly1305);
+    let sealed = encrypt_string("hello", &key, b"ctx").unwrap();
+    assert!(sealed.starts_with("$gk$v=2$c=xchacha20-poly1305$k=wrapped$"));
+    assert_eq!(
+      inspect(&sealed).unwrap(),
+      Format::Envelope(Header {
+        version: ENVELOPE_VERSION,
+        cipher: Cipher::XChaCha20Poly1305,
+        kdf: Kdf::Wrapped,
+      })
+    );
+  }
+
+  #[test]
+  fn envelopes_are_bound_to_their_context() {
+    for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+      let keys = KeyRing::new(CipherKey::generate().with_cipher(cipher));
+      let context = associated_data("id-1", "note", "alice");
+      let sealed = encrypt_string("hello", keys.primary(), &context).unwrap();
+      assert_eq!(decrypt_string(&sealed, &keys, &context).unwrap(), "hello");
+
+      for swapped in [
+        associated_data("id-2", "note", "alice"),
+        associated_data("id-1", "card", "alice"),
+        associated_data("id-1", "note", "bob"),
+      ] {
+        let err = decrypt_string(&sealed, &keys, &swapped).unwrap_err();
+        assert!(err.is::<AuthenticationFailed>());
+      }
+
+      // Only an envelope that parses, opened with the key its header names,
+      // gets as far as the tag.
+      let unrelated =
+        KeyRing::new(CipherKey::derive("hunter2", &[7; SALT_SIZE], &TEST_PARAMS, None).unwrap());
+      for (data, keys) in [
+        ("not an envelope", &keys),
+        (&sealed[..sealed.len() - 1], &keys),
+        (&sealed, &unrelated),
+      ] {
+        let err = decrypt_string(data, keys, &context).unwrap_err();
+        assert!(!err.is::<AuthenticationFailed>(), "{}", err);
+      }
+    }
+  }
+
+  #[test]
+  fn vault_keys_unwrap_only_with_their_master_key() {
+    let master = CipherKey::derive("hunter2", &[7; SALT_SIZE], &TEST_PARAMS, None).unwrap();
+    let data_key = CipherKey::generate();
+    let wrapped = wrap_key(&data_key, &master, b"header").unwrap();
+
+    let unwrapped = unwrap_key(&wrapped, &master, b"header").unwrap();
+    let sealed = encrypt_string("hello", &data_key, b"").unwrap();
+    let keys = KeyRing::new(unwrapped);
+    assert_eq!(decrypt_string(&sealed, &keys, b"").unwrap(), "hello");
+
+    let other = CipherKey::derive("hunter3", &[7; SALT_SIZE], &TEST_PARAMS, None).unwrap();
+    assert!(unwrap_key(&wrapped, &other, b"header").is_err());
+    assert!(unwrap_key(&wrapped, &master, b"other").is_err());
+  }
+
+  #[test]
+  fn legacy_entries_open_with_the_legacy_key() {
+    let legacy = legacy_key("hunter2");
+    let sealed = STANDARD.encode(seal(Cipher::Aes256Gcm, &legacy, b"old", &[]).unwrap());
+
+    let keys = KeyRing::new(CipherKey::generate()).with_legacy(legacy);
+    assert_eq!(decrypt_bytes(&sealed, &keys, b"ignored").unwrap(), b"old");
+    assert!(keys.needs_upgrade(&sealed).unwrap());
+    assert!(decrypt_bytes(&sealed, &KeyRing::new(CipherKey::generate()), b"").is_err());
+  }
+
+  #[test]
+  fn padding_rounds_up_to_a_bucket() {
+    let padding = Padding::new(vec![256, 128, 128]).unwrap();
+    assert_eq!(padding.padded_len(0), 128);
+    assert_eq!(padding.padded_len(128), 128);
+    assert_eq!(padding.padded_len(129), 256);
+    // Past the largest bucket, multiples of it.
+    assert_eq!(padding.padded_len(257), 512);
+    assert_eq!(padding.padded_len(513), 768);
+
+    assert_eq!(Padding::new(vec![]).unwrap().padded_len(77), 77);
+    assert!(Padding::new(vec![128, 0]).is_err());
+  }
+
+  #[test]
+  fn padded_json_reads_the_same() {
+    let padding = Padding::default();
+    let json = r#"{"kind":"Note","title":"t","content":"ends in spaces  "}"#;
+
+    let padded = padding.apply(json);
+    assert_eq!(padded.len(), 128);
+    assert_eq!(Padding::strip(&padded), json);
+    assert_eq!(
+      serde_json::from_str::<serde_json::Value>(&padded).unwrap(),
+      serde_json::from_str::<serde_json::Value>(json).unwrap()
+    );
+    // Padding again does not grow an entry that is already padded.
+    assert_eq!(padding.apply(&padded), padded);
+  }
+
+  fn fixed_share() -> Share {
+    Share {
+      threshold: 2,
+      index: 1,
+      value: (0..32).collect(),
+    }
+  }
+
+  #[test]
+  fn any_threshold_shares_rebuild_the_secret() {
+    let secret: Vec<u8> = (0..32).map(|i| i * 7 + 3).collect();
+    let shares = split_secret(&secret, 5, 3).unwrap();
+
+    for a in 0..5 {
+      for b in a + 1..5 {
+        for c in b + 1..5 {
+          let picked: Vec<Share> = [a, b, c]
+            .iter()
+            .map(|&i| Share::from_words(&shares[i].to_words()).unwrap())
+            .collect();
+          assert_eq!(*combine_shares(&picked).unwrap(), secret);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn fewer_shares_than_the_threshold_fail() {
+    let shares = split_secret(&[42; 32], 5, 3).unwrap();
+    let err = combine_shares(&shares[..2]).unwrap_err();
+    assert_eq!(err.to_string(), "2 of 3 required shares given");
+  }
+
+  #[test]
+  fn share_words_round_trip() {
+    let share = fixed_share();
+    let words = share.to_words();
+    assert_eq!(words.split_whitespace().count(), 27);
+
+    let read = Share::from_words(&words).unwrap();
+    assert_eq!(read.threshold, share.threshold);
+    assert_eq!(read.index, share.index);
+    assert_eq!(read.value, share.value);
+  }
+
+  #[test]
+  fn a_changed_word_fails_the_checksum() {
+    let words = fixed_share().to_words();
+    let mut words: Vec<&str> = words.split_whitespace().collect();
+    words[10] = if words[10] == "abandon" {
+      "ability"
+    } else {
+      "abandon"
+    };
+
+    let err = Share::from_words(&words.join(" ")).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Share checksum does not match; check the words"
+    );
+  }
+
+  #[test]
+  fn non_zero_padding_is_rejected() {
+    // 37 bytes take 296 of the 297 bits in 27 words; flip the spare one.
+    let words = fixed_share().to_words();
+    let mut words: Vec<&str> = words.split_whitespace().collect();
+    let list = Language::English.word_list();
+    let last = Language::English.find_word(words[26]).unwrap();
+    words[26] = list[usize::from(last ^ 1)];
+
+    let err = Share::from_words(&words.join(" ")).err().unwrap();
+    assert_eq!(err.to_string(), "Share is malformed");
   }
-  hex
 }
@@ -0,0 +1,311 @@
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use anyhow::{Result, bail};
+use bip39::Language;
+
+use crate::core::secret::SecretString;
+
+pub const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+pub const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const DIGITS: &str = "0123456789";
+pub const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+// Characters easily misread or mistyped when copied by hand.
+pub const AMBIGUOUS: &str = "Il1|O0o`'\"";
+
+pub const MAX_LENGTH: usize = 128;
+pub const MAX_WORDS: usize = 32;
+
+// Below this share of candidates meeting the class minimums, rejection
+// sampling would take too long to be worth it.
+const MIN_ACCEPTANCE: f64 = 1e-4;
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+  pub length: usize,
+  pub lowercase: bool,
+  pub uppercase: bool,
+  pub digits: bool,
+  pub symbols: bool,
+  pub exclude_ambiguous: bool,
+  // Each enabled class appears at least this many times.
+  pub min_per_class: usize,
+}
+
+impl Default for PasswordPolicy {
+  fn default() -> Self {
+    Self {
+      length: 20,
+      lowercase: true,
+      uppercase: true,
+      digits: true,
+      symbols: true,
+      exclude_ambiguous: false,
+      min_per_class: 1,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct PassphrasePolicy {
+  pub words: usize,
+  pub separator: String,
+}
+
+impl Default for PassphrasePolicy {
+  fn default() -> Self {
+    Self {
+      words: 6,
+      separator: "-".into(),
+    }
+  }
+}
+
+pub struct Generated {
+  pub value: SecretString,
+  // Bits of entropy of the generator for this policy, not an estimate of how
+  // the result would fare against a cracker.
+  pub entropy: f64,
+}
+
+fn random_below(bound: usize) -> usize {
+  let bound = bound as u64;
+  let zone = u64::MAX - u64::MAX % bound;
+  loop {
+    let value = OsRng.next_u64();
+    if value < zone {
+      return (value % bound) as usize;
+    }
+  }
+}
+
+impl PasswordPolicy {
+  fn classes(&self) -> Vec<Vec<char>> {
+    [
+      (self.lowercase, LOWERCASE),
+      (self.uppercase, UPPERCASE),
+      (self.digits, DIGITS),
+      (self.symbols, SYMBOLS),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, chars)| {
+      chars
+        .chars()
+        .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+        .collect()
+    })
+    .collect()
+  }
+}
+
+// Number of strings of the given length over the classes in which class i
+// appears at least `min` times, built up one class at a time.
+fn count_valid(sizes: &[usize], length: usize, min: usize) -> f64 {
+  let mut ways = vec![0f64; length + 1];
+  ways[0] = 1.0;
+  for &size in sizes {
+    let mut next = vec![0f64; length + 1];
+    for (used, &count) in ways.iter().enumerate().filter(|(_, c)| **c > 0.0) {
+      for taken in min..=length - used {
+        next[used + taken] +=
+          count * binomial(used + taken, taken) * (size as f64).powi(taken as i32);
+      }
+    }
+    ways = next;
+  }
+  ways[length]
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+  (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+// Draws uniformly from the whole alphabet and retries until the class
+// minimums hold, so every valid password is equally likely.
+pub fn password(policy: &PasswordPolicy) -> Result<Generated> {
+  if policy.length == 0 || policy.length > MAX_LENGTH {
+    bail!("Password length must be between 1 and {}", MAX_LENGTH);
+  }
+  let classes = policy.classes();
+  if classes.is_empty() {
+    bail!("Enable at least one character class");
+  }
+  if classes.len() * policy.min_per_class > policy.length {
+    bail!(
+      "A {} character password cannot hold {} characters of each of {} classes",
+      policy.length,
+      policy.min_per_class,
+      classes.len()
+    );
+  }
+
+  let alphabet: Vec<char> = classes.concat();
+  let sizes: Vec<usize> = classes.iter().map(Vec::len).collect();
+  let valid = count_valid(&sizes, policy.length, policy.min_per_class);
+  let total = (alphabet.len() as f64).powi(policy.length as i32);
+  if valid / total < MIN_ACCEPTANCE {
+    bail!("Too many characters required per class for this length");
+  }
+
+  let mut value = SecretString::default();
+  loop {
+    value.clear();
+    for _ in 0..policy.length {
+      value.push(alphabet[random_below(alphabet.len())]);
+    }
+    let satisfied = classes
+      .iter()
+      .all(|class| value.chars().filter(|c| class.contains(c)).count() >= policy.min_per_class);
+    if satisfied {
+      return Ok(Generated {
+        value,
+        entropy: valid.log2(),
+      });
+    }
+  }
+}
+
+// Diceware-style words from the BIP-39 English list, 11 bits each.
+pub fn passphrase(policy: &PassphrasePolicy) -> Result<Generated> {
+  if policy.words == 0 || policy.words > MAX_WORDS {
+    bail!("Passphrase must have between 1 and {} words", MAX_WORDS);
+  }
+  let list = Language::English.word_list();
+
+  let mut value = SecretString::default();
+  for i in 0..policy.words {
+    if i > 0 {
+      policy.separator.chars().for_each(|c| value.push(c));
+    }
+    list[random_below(list.len())]
+      .chars()
+      .for_each(|c| value.push(c));
+  }
+
+  Ok(Generated {
+    value,
+    entropy: policy.words as f64 * (list.len() as f64).log2(),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn passwords_follow_the_policy() {
+    let policy = PasswordPolicy {
+      length: 32,
+      exclude_ambiguous: true,
+      min_per_class: 3,
+      ..PasswordPolicy::default()
+    };
+    for _ in 0..50 {
+      let generated = password(&policy).unwrap();
+      let value = generated.value.as_str();
+      assert_eq!(value.chars().count(), 32);
+      assert!(!value.chars().any(|c| AMBIGUOUS.contains(c)));
+      for class in [LOWERCASE, UPPERCASE, DIGITS, SYMBOLS] {
+        assert!(value.chars().filter(|c| class.contains(*c)).count() >= 3);
+      }
+    }
+
+    let digits_only = PasswordPolicy {
+      length: 8,
+      lowercase: false,
+      uppercase: false,
+      symbols: false,
+      ..PasswordPolicy::default()
+    };
+    let generated = password(&digits_only).unwrap();
+    assert!(generated.value.chars().all(|c| c.is_ascii_digit()));
+  }
+
+  #[test]
+  fn impossible_policies_are_refused() {
+    let no_classes = PasswordPolicy {
+      lowercase: false,
+      uppercase: false,
+      digits: false,
+      symbols: false,
+      ..PasswordPolicy::default()
+    };
+    for policy in [
+      PasswordPolicy {
+        length: 0,
+        ..PasswordPolicy::default()
+      },
+      PasswordPolicy {
+        length: MAX_LENGTH + 1,
+        ..PasswordPolicy::default()
+      },
+      no_classes,
+      PasswordPolicy {
+        length: 7,
+        min_per_class: 2,
+        ..PasswordPolicy::default()
+      },
+      // Possible, but too few candidates would meet it.
+      PasswordPolicy {
+        length: MAX_LENGTH,
+        min_per_class: MAX_LENGTH / 4,
+        ..PasswordPolicy::default()
+      },
+    ] {
+      assert!(password(&policy).is_err(), "{:?} was accepted", policy);
+    }
+  }
+
+  #[test]
+  fn entropy_counts_only_valid_passwords() {
+    // No minimums: every string over the alphabet counts.
+    let free = PasswordPolicy {
+      length: 10,
+      min_per_class: 0,
+      ..PasswordPolicy::default()
+    };
+    let alphabet = (26 + 26 + 10 + SYMBOLS.len()) as f64;
+    let entropy = password(&free).unwrap().entropy;
+    assert!((entropy - 10.0 * alphabet.log2()).abs() < 1e-9);
+
+    // Against brute force: classes of 2 and 3 characters, 3 long, at least
+    // one of each.
+    let sizes = [2, 3];
+    let brute = (0..5usize.pow(3))
+      .filter(|n| {
+        let picks = [n % 5, n / 5 % 5, n / 25];
+        picks.iter().any(|&p| p < 2) && picks.iter().any(|&p| p >= 2)
+      })
+      .count();
+    assert_eq!(count_valid(&sizes, 3, 1), brute as f64);
+
+    // Class minimums rule some strings out, so they cost a little entropy.
+    let fewer = password(&PasswordPolicy::default()).unwrap().entropy;
+    assert!(fewer < 20.0 * alphabet.log2());
+  }
+
+  #[test]
+  fn passphrases_use_the_word_list() {
+    let policy = PassphrasePolicy {
+      words: 5,
+      separator: " ".into(),
+    };
+    let generated = passphrase(&policy).unwrap();
+    let words: Vec<&str> = generated.value.split(' ').collect();
+    assert_eq!(words.len(), 5);
+    assert!(
+      words
+        .iter()
+        .all(|w| Language::English.find_word(w).is_some())
+    );
+    assert!((generated.entropy - 55.0).abs() < 1e-9);
+
+    for words in [0, MAX_WORDS + 1] {
+      let policy = PassphrasePolicy {
+        words,
+        ..PassphrasePolicy::default()
+      };
+      assert!(passphrase(&policy).is_err());
+    }
+  }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+// Protections for a process that holds the decrypted vault. None of them is
+// required to run; whatever could not be applied is reported so the front end
+// can tell the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+  CoreDumps,
+  Ptrace,
+  MemoryLock,
+}
+
+impl fmt::Display for Protection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Protection::CoreDumps => "disable core dumps",
+      Protection::Ptrace => "block debuggers from attaching",
+      Protection::MemoryLock => "keep key material out of swap",
+    })
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct HardeningReport {
+  pub applied: Vec<Protection>,
+  pub failed: Vec<(Protection, String)>,
+}
+
+impl HardeningReport {
+  fn record(&mut self, protection: Protection, result: Result<(), String>) {
+    match result {
+      Ok(()) => self.applied.push(protection),
+      Err(reason) => self.failed.push((protection, reason)),
+    }
+  }
+
+  pub fn warnings(&self) -> Vec<String> {
+    self
+      .failed
+      .iter()
+      .map(|(protection, reason)| format!("Could not {}: {}", protection, reason))
+      .collect()
+  }
+}
+
+// Called once by each binary before any secret is read.
+pub fn harden() -> HardeningReport {
+  let mut report = HardeningReport::default();
+  report.record(Protection::CoreDumps, sys::disable_core_dumps());
+  report.record(Protection::Ptrace, sys::block_ptrace());
+  report.record(Protection::MemoryLock, sys::probe_memory_lock());
+  report
+}
+
+// Locks the pages under a value that holds keys, so they are never written to
+// swap. Best effort: harden() has already reported whether locking works.
+// Pages are not unlocked again, as other values may share them; the kernel
+// drops the lock once the allocator hands the memory back.
+pub(crate) fn lock<T>(items: &[T]) {
+  sys::lock(items.as_ptr().cast(), std::mem::size_of_val(items));
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+  use std::io;
+
+  fn check(result: libc::c_int) -> Result<(), String> {
+    if result == 0 {
+      Ok(())
+    } else {
+      Err(io::Error::last_os_error().to_string())
+    }
+  }
+
+  // Not dumpable also keeps /proc/<pid>/mem closed to other processes of the
+  // same user; the zero core limit covers a dumpable flag reset by exec.
+  pub fn disable_core_dumps() -> Result<(), String> {
+    let limit = libc::rlimit {
+      rlim_cur: 0,
+      rlim_max: 0,
+    };
+    check(unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) })?;
+    check(unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) })
+  }
+
+  // Nothing is set here: disable_core_dumps() already made the process not
+  // dumpable, which only lets a tracer in with CAP_SYS_PTRACE. That is one
+  // prctl call away from undone, so Yama is checked as the second layer; at
+  // scope 0 it lets any process of the same user attach.
+  pub fn block_ptrace() -> Result<(), String> {
+    match unsafe { libc::prctl(libc::PR_GET_DUMPABLE, 0, 0, 0, 0) } {
+      0 => {}
+      -1 => return Err(io::Error::last_os_error().to_string()),
+      _ => return Err("the process is still dumpable".into()),
+    }
+    match std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope") {
+      Ok(scope) if scope.trim() != "0" => Ok(()),
+      Ok(_) => Err(
+        "Yama ptrace_scope is 0; only the dumpable flag keeps out debuggers of the same user"
+          .into(),
+      ),
+      Err(_) => Err(
+        "Yama is not enabled; only the dumpable flag keeps out debuggers of the same user".into(),
+      ),
+    }
+  }
+
+  pub fn probe_memory_lock() -> Result<(), String> {
+    let probe = Box::new([0u8; 32]);
+    let ptr = probe.as_ptr().cast();
+    check(unsafe { libc::mlock(ptr, probe.len()) })
+      .map_err(|e| format!("{} (check the RLIMIT_MEMLOCK limit)", e))?;
+    unsafe { libc::munlock(ptr, probe.len()) };
+    Ok(())
+  }
+
+  pub fn lock(ptr: *const libc::c_void, len: usize) {
+    if len > 0 {
+      unsafe { libc::mlock(ptr, len) };
+    }
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+  const UNSUPPORTED: &str = "not supported on this platform";
+
+  pub fn disable_core_dumps() -> Result<(), String> {
+    Err(UNSUPPORTED.into())
+  }
+
+  pub fn block_ptrace() -> Result<(), String> {
+    Err(UNSUPPORTED.into())
+  }
+
+  pub fn probe_memory_lock() -> Result<(), String> {
+    Err(UNSUPPORTED.into())
+  }
+
+  pub fn lock(_ptr: *const u8, _len: usize) {}
+}
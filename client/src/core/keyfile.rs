@@ -0,0 +1,133 @@
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use anyhow::{Context, Result};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use zeroize::Zeroize;
+
+pub const KEYFILE_SIZE: usize = 32;
+
+// A local file that has to be present, next to the master password, to derive
+// the vault key, like a KeePass composite key. Any file works: only its SHA-256
+// is used, so it must never change once a vault depends on it.
+pub struct Keyfile {
+  hash: [u8; 32],
+}
+
+impl Keyfile {
+  pub fn load(path: &Path) -> Result<Self> {
+    let mut contents =
+      fs::read(path).with_context(|| format!("Cannot read keyfile {}", path.display()))?;
+    let hash = Sha256::digest(&contents).into();
+    contents.zeroize();
+    Ok(Self { hash })
+  }
+
+  // Writes 256 random bits as a line of base64, refusing to replace an
+  // existing file, since that would lock out the vault it belongs to.
+  pub fn generate(path: &Path) -> Result<Self> {
+    let mut bytes = [0u8; KEYFILE_SIZE];
+    OsRng.fill_bytes(&mut bytes);
+    let mut contents = format!("{}\n", STANDARD.encode(bytes));
+    bytes.zeroize();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let written = options
+      .open(path)
+      .and_then(|mut file| file.write_all(contents.as_bytes()))
+      .with_context(|| format!("Cannot write keyfile {}", path.display()));
+
+    let hash = Sha256::digest(contents.as_bytes()).into();
+    contents.zeroize();
+    written?;
+    Ok(Self { hash })
+  }
+
+  pub(crate) fn secret(&self) -> &[u8] {
+    &self.hash
+  }
+}
+
+impl Drop for Keyfile {
+  fn drop(&mut self) {
+    self.hash.zeroize();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::crypto::{self, CipherKey, KdfParams, KeyRing};
+
+  const TEST_PARAMS: KdfParams = KdfParams {
+    m_cost: 64,
+    t_cost: 1,
+    p_cost: 1,
+  };
+
+  fn scratch_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("gk-keyfile-{}", uuid::Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn generated_keyfiles_load_back_and_are_never_replaced() {
+    let dir = scratch_dir();
+    let path = dir.join("vault.key");
+
+    let generated = Keyfile::generate(&path).unwrap();
+    let contents = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+      STANDARD.decode(contents.trim_end()).unwrap().len(),
+      KEYFILE_SIZE
+    );
+    assert_eq!(Keyfile::load(&path).unwrap().secret(), generated.secret());
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = fs::metadata(&path).unwrap().permissions().mode();
+      assert_eq!(mode & 0o777, 0o600);
+    }
+
+    assert!(Keyfile::generate(&path).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn the_keyfile_is_mixed_into_the_derived_key() {
+    let dir = scratch_dir();
+    fs::write(dir.join("a"), b"first keyfile").unwrap();
+    fs::write(dir.join("b"), b"second keyfile").unwrap();
+    let first = Keyfile::load(&dir.join("a")).unwrap();
+    let second = Keyfile::load(&dir.join("b")).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+
+    let salt = [7; crypto::SALT_SIZE];
+    let derive = |keyfile| CipherKey::derive("hunter2", &salt, &TEST_PARAMS, keyfile).unwrap();
+
+    let data_key = CipherKey::generate();
+    let wrapped = crypto::wrap_key(&data_key, &derive(Some(&first)), b"header").unwrap();
+    let sealed = crypto::encrypt_string("hello", &data_key, b"").unwrap();
+
+    let unwrapped = crypto::unwrap_key(&wrapped, &derive(Some(&first)), b"header").unwrap();
+    let keys = KeyRing::new(unwrapped);
+    assert_eq!(
+      crypto::decrypt_string(&sealed, &keys, b"").unwrap(),
+      "hello"
+    );
+
+    // The password alone, or with another keyfile, gives another key.
+    assert!(crypto::unwrap_key(&wrapped, &derive(None), b"header").is_err());
+    assert!(crypto::unwrap_key(&wrapped, &derive(Some(&second)), b"header").is_err());
+  }
+}
@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::core::crypto::CipherKey;
+use crate::core::models::{MANIFEST_ID, MANIFEST_TYPE, Secret};
+
+// The client's own account of which entries the vault holds and which
+// revision of each it wrote last. The server only stores it; since it cannot
+// forge the MAC, dropping an entry or serving an older ciphertext shows up as
+// a mismatch on the next sync.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+  pub revision: u64,
+  pub entries: BTreeMap<String, EntryRevision>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryRevision {
+  pub revision: u64,
+  // SHA-256 of the stored ciphertext; none once the entry was deleted, so a
+  // deleted entry coming back is caught as well.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub digest: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedManifest {
+  manifest: String,
+  mac: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+  Missing(String),
+  Stale(String),
+  ManifestRolledBack { seen: u64, served: u64 },
+  ManifestInvalid,
+  // Entries the vault key no longer opens; accepting the vault state cannot
+  // fix them.
+  Tampered(String),
+  Unreadable { id: String, reason: String },
+}
+
+impl fmt::Display for IntegrityIssue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      IntegrityIssue::Missing(id) => write!(f, "Entry {} is missing from the server", id),
+      IntegrityIssue::Stale(id) => write!(f, "Entry {} is not the revision last written", id),
+      IntegrityIssue::ManifestRolledBack { seen, served } => write!(
+        f,
+        "Server returned vault manifest revision {}, older than revision {} seen before",
+        served, seen
+      ),
+      IntegrityIssue::ManifestInvalid => f.write_str("Vault manifest failed verification"),
+      IntegrityIssue::Tampered(id) => {
+        write!(f, "Entry {} was tampered with, swapped or relabelled", id)
+      }
+      IntegrityIssue::Unreadable { id, reason } => {
+        write!(f, "Entry {} cannot be read: {}", id, reason)
+      }
+    }
+  }
+}
+
+fn digest(data: &str) -> String {
+  STANDARD.encode(Sha256::digest(data.as_bytes()))
+}
+
+impl Manifest {
+  pub fn record_write(&mut self, sec: &Secret) {
+    let entry = self.entries.entry(sec.id.clone()).or_default();
+    entry.revision += 1;
+    entry.digest = Some(digest(&sec.data));
+  }
+
+  pub fn record_delete(&mut self, id: &str) {
+    let entry = self.entries.entry(id.to_string()).or_default();
+    entry.revision += 1;
+    entry.digest = None;
+  }
+
+  // Starts tracking entries written before the manifest existed. Returns
+  // whether any were added.
+  pub fn adopt(&mut self, secrets: &[Secret]) -> bool {
+    let mut changed = false;
+    for sec in secrets.iter().filter(|s| !s.is_control()) {
+      if !self.entries.contains_key(&sec.id) {
+        self.record_write(sec);
+        changed = true;
+      }
+    }
+    changed
+  }
+
+  pub fn check(&self, secrets: &[Secret]) -> Vec<IntegrityIssue> {
+    let served: HashMap<&str, &Secret> = secrets
+      .iter()
+      .filter(|s| !s.is_control())
+      .map(|s| (s.id.as_str(), s))
+      .collect();
+
+    let mut issues = Vec::new();
+    for (id, entry) in &self.entries {
+      match (&entry.digest, served.get(id.as_str())) {
+        (Some(_), None) => issues.push(IntegrityIssue::Missing(id.clone())),
+        (Some(expected), Some(sec)) if *expected != digest(&sec.data) => {
+          issues.push(IntegrityIssue::Stale(id.clone()))
+        }
+        (None, Some(_)) => issues.push(IntegrityIssue::Stale(id.clone())),
+        _ => {}
+      }
+    }
+    issues
+  }
+
+  pub fn to_record(&self, key: &CipherKey, owner: &str, context: &[u8]) -> Result<Secret> {
+    let manifest = serde_json::to_string(self)?;
+    let signed = SignedManifest {
+      mac: key.authenticate(context, manifest.as_bytes()),
+      manifest,
+    };
+
+    Ok(Secret {
+      id: MANIFEST_ID.into(),
+      user_login: owner.into(),
+      secret_type: MANIFEST_TYPE.into(),
+      data: serde_json::to_string(&signed)?,
+      created_at: None,
+      updated_at: None,
+    })
+  }
+
+  pub fn from_record(sec: &Secret, key: &CipherKey, context: &[u8]) -> Result<Self> {
+    let signed: SignedManifest = serde_json::from_str(&sec.data).context("Invalid manifest")?;
+    key.verify(context, signed.manifest.as_bytes(), &signed.mac)?;
+    serde_json::from_str(&signed.manifest).context("Invalid manifest")
+  }
+}
+
+// The last manifest seen on this machine, one file per server and user. It is
+// what catches the server serving an older manifest together with older
+// entries, so a copy that no longer parses is an error rather than no copy.
+pub async fn load_local(path: &Path) -> Result<Option<Manifest>> {
+  let contents = match fs::read_to_string(path).await {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => {
+      return Err(e).with_context(|| format!("Cannot read vault manifest {}", path.display()));
+    }
+  };
+  serde_json::from_str(&contents).map(Some).with_context(|| {
+    format!(
+      "Vault manifest {} is damaged; delete it to trust the server's copy",
+      path.display()
+    )
+  })
+}
+
+// Written to a private file next to the old copy and renamed over it, so a
+// crash never leaves a half-written manifest behind.
+pub async fn save_local(path: &Path, manifest: &Manifest) -> Result<()> {
+  if load_local(path)
+    .await?
+    .is_some_and(|seen| seen.revision > manifest.revision)
+  {
+    return Ok(());
+  }
+
+  let dir = path.parent().context("Invalid manifest path")?;
+  let mut builder = fs::DirBuilder::new();
+  builder.recursive(true);
+  #[cfg(unix)]
+  builder.mode(0o700);
+  builder.create(dir).await?;
+
+  let temp = dir.join(format!(".{}.tmp", uuid::Uuid::new_v4()));
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  options.mode(0o600);
+  let contents = serde_json::to_string(manifest)?;
+  let written = async {
+    let mut file = options.open(&temp).await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.sync_all().await?;
+    fs::rename(&temp, path).await
+  }
+  .await;
+  if written.is_err() {
+    let _ = fs::remove_file(&temp).await;
+  }
+  written.with_context(|| format!("Cannot write vault manifest {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::models::VAULT_HEADER_TYPE;
+
+  fn entry(id: &str, data: &str) -> Secret {
+    Secret {
+      id: id.into(),
+      user_login: "alice".into(),
+      secret_type: "note".into(),
+      data: data.into(),
+      created_at: None,
+      updated_at: None,
+    }
+  }
+
+  #[test]
+  fn check_flags_missing_stale_and_resurrected_entries() {
+    let a = entry("a", "sealed a");
+    let b = entry("b", "sealed b");
+    let c = entry("c", "sealed c");
+
+    let mut manifest = Manifest::default();
+    assert!(manifest.adopt(&[a.clone(), b.clone(), c.clone()]));
+    assert!(!manifest.adopt(&[a.clone(), b.clone()]));
+    manifest.record_delete("c");
+    assert!(manifest.check(&[a.clone(), b.clone()]).is_empty());
+
+    let older_b = entry("b", "older sealed b");
+    let header = Secret {
+      secret_type: VAULT_HEADER_TYPE.into(),
+      ..entry("__vault__", "header")
+    };
+    assert_eq!(
+      manifest.check(&[older_b, c, header]),
+      vec![
+        IntegrityIssue::Missing("a".into()),
+        IntegrityIssue::Stale("b".into()),
+        IntegrityIssue::Stale("c".into()),
+      ]
+    );
+
+    // Writing an entry again moves its expected digest along.
+    let new_b = entry("b", "new sealed b");
+    manifest.record_write(&new_b);
+    assert_eq!(manifest.entries["b"].revision, 2);
+    assert!(manifest.check(&[a, new_b]).is_empty());
+  }
+
+  #[test]
+  fn records_verify_only_under_their_key_and_context() {
+    let key = CipherKey::generate();
+    let mut manifest = Manifest {
+      revision: 3,
+      ..Manifest::default()
+    };
+    manifest.adopt(&[entry("a", "sealed a")]);
+
+    let record = manifest.to_record(&key, "alice", b"ctx").unwrap();
+    assert_eq!(record.id, MANIFEST_ID);
+    assert_eq!(
+      Manifest::from_record(&record, &key, b"ctx").unwrap(),
+      manifest
+    );
+
+    assert!(Manifest::from_record(&record, &CipherKey::generate(), b"ctx").is_err());
+    assert!(Manifest::from_record(&record, &key, b"other").is_err());
+
+    // The server cannot bump the revision or drop an entry without the key.
+    let mut signed: SignedManifest = serde_json::from_str(&record.data).unwrap();
+    signed.manifest = signed.manifest.replace("\"revision\":3", "\"revision\":4");
+    let forged = Secret {
+      data: serde_json::to_string(&signed).unwrap(),
+      ..record
+    };
+    assert!(Manifest::from_record(&forged, &key, b"ctx").is_err());
+  }
+
+  #[tokio::test]
+  async fn the_local_copy_never_goes_back_a_revision() {
+    let path = std::env::temp_dir()
+      .join(format!("gk-manifest-{}", uuid::Uuid::new_v4()))
+      .join("alice.json");
+    assert_eq!(load_local(&path).await.unwrap(), None);
+
+    let newer = Manifest {
+      revision: 5,
+      ..Manifest::default()
+    };
+    save_local(&path, &newer).await.unwrap();
+    let older = Manifest {
+      revision: 4,
+      ..Manifest::default()
+    };
+    save_local(&path, &older).await.unwrap();
+    assert_eq!(load_local(&path).await.unwrap(), Some(newer));
+
+    // Only the manifest itself is left behind, readable by its owner alone.
+    let dir = path.parent().unwrap();
+    let mut files = fs::read_dir(dir).await.unwrap();
+    let mut names = Vec::new();
+    while let Some(file) = files.next_entry().await.unwrap() {
+      names.push(file.file_name());
+    }
+    assert_eq!(names, ["alice.json"]);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = fs::metadata(&path).await.unwrap().permissions().mode();
+      assert_eq!(mode & 0o777, 0o600);
+    }
+
+    fs::write(&path, "{\"revision\":").await.unwrap();
+    assert!(load_local(&path).await.is_err());
+    assert!(save_local(&path, &older).await.is_err());
+
+    fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+  }
+}
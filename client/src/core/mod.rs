@@ -1,9 +1,21 @@
+#[cfg(unix)]
+pub mod agent;
 pub mod app;
+pub mod bundle;
 pub mod client;
 pub mod crypto;
+pub mod generator;
+pub mod hardening;
+pub mod keyfile;
+pub mod manifest;
 pub mod models;
+pub mod recovery;
+pub mod secret;
+pub mod sshkey;
+pub mod totp;
 
-pub use app::GopherApp;
-pub use client::GopherClient;
+pub use app::{GopherApp, KeyfileRequired, WrongPassword, format_remaining};
+pub use client::{GopherClient, SessionExpired};
 pub use crypto::{decrypt_string, encrypt_string, generate_id};
 pub use models::*;
+pub use secret::{SecretString, Sensitive};
@@ -1,4 +1,20 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use zeroize::Zeroize;
+
+use crate::core::crypto::{Cipher, KdfParams};
+use crate::core::manifest::IntegrityIssue;
+use crate::core::secret::Sensitive;
+use crate::core::totp::TotpAlgorithm;
+
+pub const VAULT_HEADER_ID: &str = "__vault__";
+pub const VAULT_HEADER_TYPE: &str = "vault";
+pub const IDENTITY_ID: &str = "__identity__";
+pub const IDENTITY_TYPE: &str = "identity";
+pub const MANIFEST_ID: &str = "__manifest__";
+pub const MANIFEST_TYPE: &str = "manifest";
+pub const FILE_TYPE: &str = "file";
+pub const FILE_CHUNK_TYPE: &str = "file-chunk";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthRequest {
@@ -6,12 +22,30 @@ pub struct AuthRequest {
   pub password: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePasswordRequest {
+  pub password: String,
+  pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryRequest {
+  pub recovery: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoverRequest {
+  pub login: String,
+  pub recovery: String,
+  pub new_password: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Token {
   pub token: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Secret {
   pub id: String,
   pub user_login: String,
@@ -22,7 +56,18 @@ pub struct Secret {
   pub updated_at: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Secret {
+  // The vault header and the manifest describe the vault rather than hold
+  // anything sealed under the vault key. File chunks are sealed under their
+  // file's own key and checked against that entry instead.
+  pub fn is_control(&self) -> bool {
+    self.secret_type == VAULT_HEADER_TYPE
+      || self.secret_type == MANIFEST_TYPE
+      || self.secret_type == FILE_CHUNK_TYPE
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Zeroize)]
 #[serde(tag = "kind")]
 pub enum SecretPayload {
   Password {
@@ -30,10 +75,14 @@ pub enum SecretPayload {
     login: String,
     password: String,
     url: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<CustomField>,
   },
   Note {
     title: String,
     content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<CustomField>,
   },
   Card {
     title: String,
@@ -41,18 +90,158 @@ pub enum SecretPayload {
     number: String,
     expiry: String,
     cvv: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<CustomField>,
   },
+  Totp {
+    title: String,
+    issuer: Option<String>,
+    account: Option<String>,
+    // Canonical base32, as in the otpauth URI.
+    secret: String,
+    algorithm: TotpAlgorithm,
+    digits: u32,
+    period: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<CustomField>,
+  },
+  SshKey {
+    title: String,
+    // OpenSSH format, protected by `passphrase` when there is one.
+    private_key: String,
+    passphrase: Option<String>,
+    // Derived from the private key on import, so listing keys needs no
+    // passphrase.
+    public_key: String,
+    comment: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<CustomField>,
+  },
+  // The content lives in FILE_CHUNK_TYPE records with ids "<id>/<index>",
+  // sealed under `key` so they never pass through memory all at once.
+  File {
+    title: String,
+    name: String,
+    size: u64,
+    chunks: u32,
+    key: String,
+    // Base64 SHA-256 of the whole content.
+    digest: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<CustomField>,
+  },
+}
+
+impl SecretPayload {
+  pub fn custom_fields(&self) -> &[CustomField] {
+    match self {
+      SecretPayload::Password { fields, .. }
+      | SecretPayload::Note { fields, .. }
+      | SecretPayload::Card { fields, .. }
+      | SecretPayload::Totp { fields, .. }
+      | SecretPayload::SshKey { fields, .. }
+      | SecretPayload::File { fields, .. } => fields,
+    }
+  }
+
+  pub fn with_fields(mut self, custom: Vec<CustomField>) -> Self {
+    match &mut self {
+      SecretPayload::Password { fields, .. }
+      | SecretPayload::Note { fields, .. }
+      | SecretPayload::Card { fields, .. }
+      | SecretPayload::Totp { fields, .. }
+      | SecretPayload::SshKey { fields, .. }
+      | SecretPayload::File { fields, .. } => *fields = custom,
+    }
+    self
+  }
+}
+
+// Anything an entry's fixed shape has no room for, such as a PIN or the
+// answer to a security question.
+#[derive(Debug, Clone, Serialize, Deserialize, Zeroize)]
+pub struct CustomField {
+  pub name: String,
+  pub value: String,
+  #[serde(default)]
+  pub kind: FieldKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Zeroize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+  Hidden,
+  #[default]
+  Visible,
+  Url,
+  Date,
+}
+
+// Written the way `--field NAME:KIND=VALUE` takes it.
+impl fmt::Display for FieldKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      FieldKind::Hidden => "hidden",
+      FieldKind::Visible => "visible",
+      FieldKind::Url => "url",
+      FieldKind::Date => "date",
+    })
+  }
 }
 
 #[derive(Debug, Clone)]
 pub struct DecryptedSecret {
   pub id: String,
   pub secret_type: String,
-  pub payload: SecretPayload,
+  pub payload: Sensitive<SecretPayload>,
   pub created_at: Option<String>,
   pub updated_at: Option<String>,
 }
 
+#[derive(Debug, Default)]
+pub struct SyncReport {
+  pub secrets: Vec<DecryptedSecret>,
+  pub upgraded: Vec<String>,
+  pub issues: Vec<IntegrityIssue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultHeader {
+  pub kdf: KdfParams,
+  #[serde(default)]
+  pub cipher: Cipher,
+  pub salt: String,
+  // The random vault key sealed under the password-derived key. Further
+  // unlock methods get their own wrapped copy next to this one.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub wrapped_key: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub canary: Option<String>,
+  // Whether the password-derived key also takes a keyfile.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub keyfile: bool,
+  // The vault key sealed under the recovery key, if one was generated.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub recovery_key: Option<String>,
+}
+
+// The user's X25519 secret key for share bundles, kept in the vault as an
+// entry of its own.
+#[derive(Serialize, Deserialize, Zeroize)]
+pub struct IdentityKey {
+  pub secret: String,
+}
+
+// A secret sealed to someone else's public key, written to a file and carried
+// over by hand.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShareBundle {
+  pub version: u8,
+  pub recipient: String,
+  pub ephemeral: String,
+  pub data: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyncRequest {
   pub secrets: Vec<Secret>,
@@ -61,5 +250,5 @@ pub struct SyncRequest {
 #[derive(Debug, Deserialize)]
 pub struct Claims {
   pub login: String,
-  exp: usize,
+  pub exp: usize,
 }
@@ -0,0 +1,92 @@
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use bip39::Mnemonic;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::core::crypto::{self, CipherKey, Share};
+use crate::core::secret::SecretString;
+
+pub const RECOVERY_KEY_SIZE: usize = 32;
+
+// A random 256-bit key that opens the vault without the master password. It is
+// written down as 24 BIP39 words, the last of which carries a checksum, so a
+// mistyped or misread word is caught before the server is asked anything.
+pub struct RecoveryKey {
+  entropy: [u8; RECOVERY_KEY_SIZE],
+}
+
+impl RecoveryKey {
+  pub fn generate() -> Self {
+    let mut entropy = [0u8; RECOVERY_KEY_SIZE];
+    OsRng.fill_bytes(&mut entropy);
+    Self { entropy }
+  }
+
+  pub fn parse(words: &str) -> Result<Self> {
+    let normalized = SecretString::new(
+      words
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" "),
+    );
+
+    let mnemonic = Mnemonic::parse_normalized(&normalized)
+      .map_err(|e| anyhow!("Invalid recovery key: {}", e))?;
+    let (mut bytes, len) = mnemonic.to_entropy_array();
+
+    let result = if len == RECOVERY_KEY_SIZE {
+      let mut entropy = [0u8; RECOVERY_KEY_SIZE];
+      entropy.copy_from_slice(&bytes[..len]);
+      Ok(Self { entropy })
+    } else {
+      Err(anyhow!("Invalid recovery key: expected 24 words"))
+    };
+
+    bytes.zeroize();
+    result
+  }
+
+  // Splits the key among trustees so that any `threshold` of them together can
+  // stand in for it.
+  pub fn split(&self, shares: u8, threshold: u8) -> Result<Vec<Share>> {
+    crypto::split_secret(&self.entropy, shares, threshold)
+  }
+
+  pub fn combine(shares: &[Share]) -> Result<Self> {
+    let secret = crypto::combine_shares(shares)?;
+    let entropy = secret
+      .as_slice()
+      .try_into()
+      .map_err(|_| anyhow!("Shares do not hold a recovery key"))?;
+    Ok(Self { entropy })
+  }
+
+  pub fn words(&self) -> SecretString {
+    let mnemonic = Mnemonic::from_entropy(&self.entropy).expect("32 bytes is valid BIP39 entropy");
+    SecretString::new(mnemonic.to_string())
+  }
+
+  pub fn cipher_key(&self) -> CipherKey {
+    CipherKey::recovery(&self.entropy)
+  }
+
+  // Proof of possession sent to the server to reset the login password. It is
+  // unrelated to the wrapping key, so the server learns nothing that opens the
+  // vault.
+  pub fn auth_hash(&self) -> SecretString {
+    let mut hasher = Sha256::new();
+    hasher.update(b"gophkeeper-recovery-auth:");
+    hasher.update(self.entropy);
+    SecretString::new(STANDARD.encode(hasher.finalize()))
+  }
+}
+
+impl Drop for RecoveryKey {
+  fn drop(&mut self) {
+    self.entropy.zeroize();
+  }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::ops::Deref;
+
+use zeroize::Zeroize;
+
+// Holds a value that must not outlive its use: the memory is wiped on drop and
+// never shows up in Debug or Display output.
+#[derive(Default, Clone)]
+pub struct Sensitive<T: Zeroize>(T);
+
+pub type SecretString = Sensitive<String>;
+
+impl<T: Zeroize> Sensitive<T> {
+  pub fn new(value: T) -> Self {
+    Self(value)
+  }
+
+  pub fn expose(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T: Zeroize> Deref for Sensitive<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T: Zeroize> From<T> for Sensitive<T> {
+  fn from(value: T) -> Self {
+    Self(value)
+  }
+}
+
+impl<T: Zeroize> Drop for Sensitive<T> {
+  fn drop(&mut self) {
+    self.0.zeroize();
+  }
+}
+
+impl<T: Zeroize> fmt::Debug for Sensitive<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("[REDACTED]")
+  }
+}
+
+impl<T: Zeroize> fmt::Display for Sensitive<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("[REDACTED]")
+  }
+}
+
+impl<T: Zeroize + PartialEq> PartialEq for Sensitive<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.0 == other.0
+  }
+}
+
+impl SecretString {
+  // Input buffers grow one keystroke at a time. Growing by hand keeps the old
+  // allocation from being freed with the text still in it.
+  pub fn push(&mut self, c: char) {
+    if self.0.len() + c.len_utf8() > self.0.capacity() {
+      let mut grown = String::with_capacity((self.0.capacity() * 2).max(32));
+      grown.push_str(&self.0);
+      self.0.zeroize();
+      self.0 = grown;
+    }
+    self.0.push(c);
+  }
+
+  pub fn pop(&mut self) -> Option<char> {
+    let c = self.0.pop()?;
+    let mut bytes = std::mem::take(&mut self.0).into_bytes();
+    bytes.spare_capacity_mut().zeroize();
+    self.0 = String::from_utf8(bytes).unwrap_or_default();
+    Some(c)
+  }
+
+  pub fn clear(&mut self) {
+    self.0.zeroize();
+  }
+}
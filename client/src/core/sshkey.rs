@@ -0,0 +1,80 @@
+use aes_gcm::aead::OsRng;
+use anyhow::{Result, anyhow, bail};
+use ssh_key::{Algorithm, HashAlg, LineEnding, PrivateKey, PublicKey};
+
+use crate::core::models::SecretPayload;
+
+// Reads an OpenSSH private key as written by ssh-keygen. A protected key is
+// only accepted with the passphrase that opens it, and is kept protected.
+pub fn import(
+  title: String,
+  private_key: &str,
+  passphrase: Option<String>,
+  comment: Option<String>,
+) -> Result<SecretPayload> {
+  let key = PrivateKey::from_openssh(private_key.trim())
+    .map_err(|e| anyhow!("Invalid OpenSSH private key: {}", e))?;
+  // The comment of a protected key is sealed along with the key itself.
+  let own_comment = match (key.is_encrypted(), passphrase.as_deref()) {
+    (true, Some(passphrase)) => key
+      .decrypt(passphrase)
+      .map_err(|_| anyhow!("Wrong passphrase for this key"))?
+      .comment()
+      .to_string(),
+    (true, None) => bail!("This key is protected; its passphrase is needed too"),
+    (false, Some(_)) => bail!("This key is not protected by a passphrase"),
+    (false, None) => key.comment().to_string(),
+  };
+
+  to_payload(title, &key, passphrase, comment.unwrap_or(own_comment))
+}
+
+// A fresh ed25519 key, commented with the title unless told otherwise.
+pub fn generate(
+  title: String,
+  passphrase: Option<String>,
+  comment: Option<String>,
+) -> Result<SecretPayload> {
+  let comment = comment.unwrap_or_else(|| title.clone());
+  let mut key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+    .map_err(|e| anyhow!("Key generation failed: {}", e))?;
+  key.set_comment(comment.clone());
+  if let Some(passphrase) = &passphrase {
+    key = key
+      .encrypt(&mut OsRng, passphrase)
+      .map_err(|e| anyhow!("Failed to protect the key: {}", e))?;
+  }
+
+  to_payload(title, &key, passphrase, comment)
+}
+
+fn to_payload(
+  title: String,
+  key: &PrivateKey,
+  passphrase: Option<String>,
+  comment: String,
+) -> Result<SecretPayload> {
+  let mut public_key = key.public_key().clone();
+  public_key.set_comment(comment.clone());
+  let private_key = key
+    .to_openssh(LineEnding::LF)
+    .map_err(|e| anyhow!("Failed to encode the key: {}", e))?;
+
+  Ok(SecretPayload::SshKey {
+    title,
+    private_key: private_key.to_string(),
+    passphrase,
+    public_key: public_key
+      .to_openssh()
+      .map_err(|e| anyhow!("Failed to encode the public key: {}", e))?,
+    comment,
+    fields: Vec::new(),
+  })
+}
+
+// "SHA256:..." as printed by ssh-keygen -l.
+pub fn fingerprint(public_key: &str) -> Result<String> {
+  let key =
+    PublicKey::from_openssh(public_key).map_err(|e| anyhow!("Invalid public key: {}", e))?;
+  Ok(key.fingerprint(HashAlg::Sha256).to_string())
+}
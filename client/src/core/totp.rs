@@ -0,0 +1,386 @@
+use anyhow::{Context, Result, anyhow, bail};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+use zeroize::Zeroize;
+
+use crate::core::models::SecretPayload;
+use crate::core::secret::SecretString;
+
+pub const DEFAULT_DIGITS: u32 = 6;
+pub const DEFAULT_PERIOD: u64 = 30;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Zeroize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TotpAlgorithm {
+  #[default]
+  Sha1,
+  Sha256,
+  Sha512,
+}
+
+impl fmt::Display for TotpAlgorithm {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      TotpAlgorithm::Sha1 => "SHA1",
+      TotpAlgorithm::Sha256 => "SHA256",
+      TotpAlgorithm::Sha512 => "SHA512",
+    })
+  }
+}
+
+impl FromStr for TotpAlgorithm {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s.to_ascii_uppercase().replace('-', "").as_str() {
+      "SHA1" => Ok(TotpAlgorithm::Sha1),
+      "SHA256" => Ok(TotpAlgorithm::Sha256),
+      "SHA512" => Ok(TotpAlgorithm::Sha512),
+      _ => Err(anyhow!("Unknown TOTP algorithm: {}", s)),
+    }
+  }
+}
+
+// Everything needed to compute codes, as read from an otpauth:// URI or a bare
+// base32 seed. The secret is kept in canonical base32.
+#[derive(Clone)]
+pub struct TotpSeed {
+  pub secret: String,
+  pub algorithm: TotpAlgorithm,
+  pub digits: u32,
+  pub period: u64,
+  pub issuer: Option<String>,
+  pub account: Option<String>,
+}
+
+impl Drop for TotpSeed {
+  fn drop(&mut self) {
+    self.secret.zeroize();
+  }
+}
+
+impl TotpSeed {
+  pub fn parse(input: &str) -> Result<Self> {
+    let input = input.trim();
+    if input
+      .get(..10)
+      .is_some_and(|scheme| scheme.eq_ignore_ascii_case("otpauth://"))
+    {
+      return Self::from_uri(input);
+    }
+    Ok(Self {
+      secret: normalize_secret(input)?,
+      algorithm: TotpAlgorithm::default(),
+      digits: DEFAULT_DIGITS,
+      period: DEFAULT_PERIOD,
+      issuer: None,
+      account: None,
+    })
+  }
+
+  // otpauth://totp/Issuer:account?secret=...&issuer=...&algorithm=...
+  fn from_uri(uri: &str) -> Result<Self> {
+    let url = Url::parse(uri).context("Invalid otpauth URI")?;
+    match url.host_str() {
+      Some(host) if host.eq_ignore_ascii_case("totp") => {}
+      Some(host) if host.eq_ignore_ascii_case("hotp") => {
+        bail!("Counter-based (HOTP) codes are not supported")
+      }
+      _ => bail!("Invalid otpauth URI: expected otpauth://totp/"),
+    }
+
+    let label = percent_decode_str(url.path().trim_start_matches('/'))
+      .decode_utf8_lossy()
+      .into_owned();
+    let (mut issuer, account) = match label.split_once(':') {
+      Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+      None => (None, label.trim().to_string()),
+    };
+
+    let mut secret = None;
+    let mut algorithm = TotpAlgorithm::default();
+    let mut digits = DEFAULT_DIGITS;
+    let mut period = DEFAULT_PERIOD;
+    for (key, value) in url.query_pairs() {
+      match key.to_ascii_lowercase().as_str() {
+        "secret" => secret = Some(normalize_secret(&value)?),
+        "issuer" => issuer = Some(value.into_owned()),
+        "algorithm" => algorithm = value.parse()?,
+        "digits" => digits = value.parse().context("Invalid digits in otpauth URI")?,
+        "period" => period = value.parse().context("Invalid period in otpauth URI")?,
+        _ => {}
+      }
+    }
+
+    let seed = Self {
+      secret: secret.context("otpauth URI has no secret")?,
+      algorithm,
+      digits,
+      period,
+      issuer: issuer.filter(|i| !i.is_empty()),
+      account: Some(account).filter(|a| !a.is_empty()),
+    };
+    check_params(seed.digits, seed.period)?;
+    Ok(seed)
+  }
+}
+
+impl TotpSeed {
+  pub fn to_payload(&self, title: String) -> SecretPayload {
+    SecretPayload::Totp {
+      title,
+      issuer: self.issuer.clone(),
+      account: self.account.clone(),
+      secret: self.secret.clone(),
+      algorithm: self.algorithm,
+      digits: self.digits,
+      period: self.period,
+      fields: Vec::new(),
+    }
+  }
+}
+
+pub fn check_params(digits: u32, period: u64) -> Result<()> {
+  if !(4..=10).contains(&digits) {
+    bail!("TOTP codes must have between 4 and 10 digits");
+  }
+  if period == 0 {
+    bail!("TOTP period must be at least one second");
+  }
+  Ok(())
+}
+
+// Seeds are often shown in lowercase, grouped with spaces or padded.
+pub fn normalize_secret(secret: &str) -> Result<String> {
+  let normalized: String = secret
+    .chars()
+    .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+    .map(|c| c.to_ascii_uppercase())
+    .collect();
+  let mut bytes = decode_base32(&normalized)?;
+  bytes.zeroize();
+  Ok(normalized)
+}
+
+fn decode_base32(text: &str) -> Result<Vec<u8>> {
+  let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+  let mut buffer = 0u64;
+  let mut bits = 0;
+  for c in text.bytes() {
+    let value = BASE32_ALPHABET
+      .iter()
+      .position(|&a| a == c)
+      .ok_or_else(|| anyhow!("TOTP secret is not valid base32"))?;
+    buffer = (buffer << 5) | value as u64;
+    bits += 5;
+    if bits >= 8 {
+      bits -= 8;
+      bytes.push((buffer >> bits) as u8);
+    }
+  }
+  buffer.zeroize();
+  if bytes.is_empty() {
+    bail!("TOTP secret is empty");
+  }
+  Ok(bytes)
+}
+
+fn hmac<M: Mac + KeyInit>(key: &[u8], counter: u64) -> Vec<u8> {
+  let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any size");
+  mac.update(&counter.to_be_bytes());
+  mac.finalize().into_bytes().to_vec()
+}
+
+// RFC 6238: HOTP over the number of periods since the epoch, with the RFC 4226
+// dynamic truncation.
+pub fn code_at(
+  secret: &str,
+  algorithm: TotpAlgorithm,
+  digits: u32,
+  period: u64,
+  time: u64,
+) -> Result<SecretString> {
+  check_params(digits, period)?;
+  let mut key = decode_base32(secret)?;
+  let counter = time / period;
+  let mut digest = match algorithm {
+    TotpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(&key, counter),
+    TotpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(&key, counter),
+    TotpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(&key, counter),
+  };
+  key.zeroize();
+
+  let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+  let binary = u32::from_be_bytes([
+    digest[offset] & 0x7f,
+    digest[offset + 1],
+    digest[offset + 2],
+    digest[offset + 3],
+  ]);
+  digest.zeroize();
+
+  let code = binary as u64 % 10u64.pow(digits);
+  Ok(SecretString::new(format!(
+    "{:0width$}",
+    code,
+    width = digits as usize
+  )))
+}
+
+pub struct TotpCode {
+  pub code: SecretString,
+  pub remaining: u64,
+  pub period: u64,
+}
+
+pub fn current_code(payload: &SecretPayload) -> Result<TotpCode> {
+  let SecretPayload::Totp {
+    secret,
+    algorithm,
+    digits,
+    period,
+    ..
+  } = payload
+  else {
+    bail!("Secret is not a TOTP entry");
+  };
+  let time = unix_time();
+  Ok(TotpCode {
+    code: code_at(secret, *algorithm, *digits, *period, time)?,
+    remaining: remaining(*period, time),
+    period: *period,
+  })
+}
+
+pub fn unix_time() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default()
+}
+
+// Seconds until the code shown at `time` rolls over.
+pub fn remaining(period: u64, time: u64) -> u64 {
+  let period = period.max(1);
+  period - time % period
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The RFC 6238 seeds, "1234567890" repeated to 20, 32 and 64 bytes.
+  const SHA1_SEED: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+  const SHA256_SEED: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+  const SHA512_SEED: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+
+  // RFC 6238 appendix B.
+  const VECTORS: [(u64, &str, &str, &str); 6] = [
+    (59, "94287082", "46119246", "90693936"),
+    (1111111109, "07081804", "68084774", "25091201"),
+    (1111111111, "14050471", "67062674", "99943326"),
+    (1234567890, "89005924", "91819424", "93441116"),
+    (2000000000, "69279037", "90698825", "38618901"),
+    (20000000000, "65353130", "77737706", "47863826"),
+  ];
+
+  fn code(seed: &str, algorithm: TotpAlgorithm, time: u64) -> String {
+    code_at(seed, algorithm, 8, 30, time)
+      .unwrap()
+      .expose()
+      .clone()
+  }
+
+  #[test]
+  fn rfc6238_vectors() {
+    for (time, sha1, sha256, sha512) in VECTORS {
+      assert_eq!(code(SHA1_SEED, TotpAlgorithm::Sha1, time), sha1);
+      assert_eq!(code(SHA256_SEED, TotpAlgorithm::Sha256, time), sha256);
+      assert_eq!(code(SHA512_SEED, TotpAlgorithm::Sha512, time), sha512);
+    }
+  }
+
+  #[test]
+  fn six_digit_codes_keep_leading_zeros() {
+    let code = code_at(SHA1_SEED, TotpAlgorithm::Sha1, 6, 30, 1111111109).unwrap();
+    assert_eq!(code.expose(), "081804");
+  }
+
+  #[test]
+  fn bare_seeds_are_normalized() {
+    let seed = TotpSeed::parse(" gezd gnbv-gy3t qojq gezd gnbv gy3t qojq== ").unwrap();
+    assert_eq!(seed.secret, SHA1_SEED);
+    assert_eq!(seed.algorithm, TotpAlgorithm::Sha1);
+    assert_eq!((seed.digits, seed.period), (DEFAULT_DIGITS, DEFAULT_PERIOD));
+    assert!(TotpSeed::parse("not base32!").is_err());
+  }
+
+  #[test]
+  fn uri_with_issuer_in_the_label() {
+    let seed = TotpSeed::parse(&format!(
+      "otpauth://totp/ACME%20Co:john.doe%40example.com?secret={}&algorithm=SHA256&digits=8&period=60",
+      SHA256_SEED
+    ))
+    .unwrap();
+    assert_eq!(seed.issuer.as_deref(), Some("ACME Co"));
+    assert_eq!(seed.account.as_deref(), Some("john.doe@example.com"));
+    assert_eq!(seed.algorithm, TotpAlgorithm::Sha256);
+    assert_eq!((seed.digits, seed.period), (8, 60));
+  }
+
+  #[test]
+  fn issuer_in_the_query_wins_over_the_label() {
+    let seed = TotpSeed::parse(&format!(
+      "otpauth://totp/Old:alice?secret={}&issuer=New%20Name",
+      SHA1_SEED
+    ))
+    .unwrap();
+    assert_eq!(seed.issuer.as_deref(), Some("New Name"));
+    assert_eq!(seed.account.as_deref(), Some("alice"));
+
+    let seed = TotpSeed::parse(&format!(
+      "otpauth://totp/alice?secret={}&issuer=Svc",
+      SHA1_SEED
+    ))
+    .unwrap();
+    assert_eq!(seed.issuer.as_deref(), Some("Svc"));
+    assert_eq!(seed.account.as_deref(), Some("alice"));
+  }
+
+  #[test]
+  fn bad_uris_are_rejected() {
+    let hotp = TotpSeed::parse(&format!(
+      "otpauth://hotp/alice?secret={}&counter=1",
+      SHA1_SEED
+    ));
+    assert_eq!(
+      hotp.err().unwrap().to_string(),
+      "Counter-based (HOTP) codes are not supported"
+    );
+    assert!(TotpSeed::parse("otpauth://totp/alice?issuer=Svc").is_err());
+    assert!(TotpSeed::parse(&format!("otpauth://totp/a?secret={}&digits=3", SHA1_SEED)).is_err());
+    assert!(
+      TotpSeed::parse(&format!(
+        "otpauth://totp/a?secret={}&algorithm=MD5",
+        SHA1_SEED
+      ))
+      .is_err()
+    );
+  }
+
+  #[test]
+  fn remaining_counts_down_to_the_next_period() {
+    assert_eq!(remaining(30, 59), 1);
+    assert_eq!(remaining(30, 60), 30);
+  }
+}
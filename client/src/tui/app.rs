@@ -1,6 +1,13 @@
-use crate::core::GopherApp;
-use crate::core::models::{DecryptedSecret, SecretPayload};
-
+use crate::core::generator::{self, PasswordPolicy};
+use crate::core::keyfile::Keyfile;
+use crate::core::models::{DecryptedSecret, FieldKind, SecretPayload};
+use crate::core::secret::SecretString;
+use crate::core::sshkey;
+use crate::core::totp::{self, TotpSeed};
+use crate::core::{GopherApp, KeyfileRequired, SessionExpired, WrongPassword};
+
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
@@ -12,6 +19,8 @@ pub enum Screen {
   Menu,
   Secrets,
   AddSecret,
+  ChangePassword,
+  RecoveryKey,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,11 +36,26 @@ pub enum LoginStep {
   ConfirmPassword,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MasterField {
+  Password,
+  Keyfile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordStep {
+  Current,
+  New,
+  Confirm,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddKind {
   Password,
   Note,
   Card,
+  Totp,
+  SshKey,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -55,18 +79,26 @@ pub struct TuiApp {
   pub selected: usize,
 
   pub username: String,
-  pub password: String,
-  pub confirm_password: String,
+  pub password: SecretString,
+  pub confirm_password: SecretString,
   pub login_step: LoginStep,
 
+  pub keyfile_path: String,
+  pub master_field: MasterField,
+
+  pub new_password: SecretString,
+  pub password_step: PasswordStep,
+
+  pub recovery_words: SecretString,
+
   pub add_kind: AddKind,
   pub add_field: AddField,
 
   pub title: String,
-  pub field1: String,
-  pub field2: String,
-  pub field3: String,
-  pub field4: String,
+  pub field1: SecretString,
+  pub field2: SecretString,
+  pub field3: SecretString,
+  pub field4: SecretString,
 
   pub detail_selected: usize,
 
@@ -80,9 +112,16 @@ impl TuiApp {
     let mut api = GopherApp::new("http://localhost:8080".into());
     let rt = Runtime::new()?;
 
-    let auto_login = rt.block_on(api.try_auto_login()).is_ok();
+    let auto_login = rt.block_on(api.try_auto_login());
+    let expired = matches!(&auto_login, Err(e) if e.is::<SessionExpired>());
+    let unusable = match &auto_login {
+      Err(e) if !expired => Some(format!("Stored session not used: {}", e)),
+      _ => None,
+    };
+    let auto_login = matches!(auto_login, Ok(true));
+    let username = api.get_current_user().unwrap_or_default().to_string();
 
-    Ok(Self {
+    let mut app = Self {
       screen: if auto_login {
         Screen::MasterPassword
       } else {
@@ -97,21 +136,36 @@ impl TuiApp {
       rt,
       secrets: vec![],
       selected: 0,
-      username: String::new(),
-      password: String::new(),
-      confirm_password: String::new(),
-      login_step: LoginStep::Username,
+      username,
+      password: SecretString::default(),
+      confirm_password: SecretString::default(),
+      login_step: if expired {
+        LoginStep::Password
+      } else {
+        LoginStep::Username
+      },
+      keyfile_path: String::new(),
+      master_field: MasterField::Password,
+      new_password: SecretString::default(),
+      password_step: PasswordStep::Current,
+      recovery_words: SecretString::default(),
       add_kind: AddKind::Password,
       add_field: AddField::Title,
       title: String::new(),
-      field1: String::new(),
-      field2: String::new(),
-      field3: String::new(),
-      field4: String::new(),
+      field1: SecretString::default(),
+      field2: SecretString::default(),
+      field3: SecretString::default(),
+      field4: SecretString::default(),
       detail_selected: 0,
       notification: None,
       should_quit: false,
-    })
+    };
+    if expired {
+      app.notify_error("Session expired, log in again");
+    } else if let Some(msg) = unusable {
+      app.notify_error(msg);
+    }
+    Ok(app)
   }
 
   pub fn notify_success(&mut self, msg: impl Into<String>) {
@@ -152,10 +206,6 @@ impl TuiApp {
       .block_on(self.api.login(self.username.clone(), self.password.clone()))
     {
       Ok(_) => {
-        self.api.set_current_user(self.username.clone());
-
-        self.api.set_master_password(self.password.clone());
-
         self.screen = Screen::Menu;
         self.input_mode = InputMode::Normal;
 
@@ -164,10 +214,40 @@ impl TuiApp {
         self.username.clear();
         self.password.clear();
       }
+      // Logged in, but the vault stays locked until the keyfile is given.
+      Err(e) if e.is::<KeyfileRequired>() => {
+        self.username.clear();
+        self.password.clear();
+        self.ask_for_keyfile();
+      }
       Err(e) => self.notify_error(format!("Login failed: {}", e)),
     }
   }
 
+  pub fn migrate_account(&mut self) {
+    if self.username.is_empty() || self.password.is_empty() {
+      self.notify_error("Enter username and password to migrate");
+      return;
+    }
+
+    match self.rt.block_on(
+      self
+        .api
+        .migrate_account(self.username.clone(), self.password.clone()),
+    ) {
+      Ok(_) => {
+        self.screen = Screen::Menu;
+        self.input_mode = InputMode::Normal;
+
+        self.notify_success("Account migrated");
+
+        self.username.clear();
+        self.password.clear();
+      }
+      Err(e) => self.notify_error(format!("Migration failed: {}", e)),
+    }
+  }
+
   pub fn register(&mut self) {
     if self.password != self.confirm_password {
       self.notify("Passwords do not match", 3);
@@ -190,19 +270,56 @@ impl TuiApp {
   pub fn logout(&mut self) {
     let _ = self.rt.block_on(self.api.logout());
     self.secrets.clear();
+    self.reset_password_fields();
+    self.reset_add_fields();
+    self.recovery_words.clear();
+    self.keyfile_path.clear();
+    self.master_field = MasterField::Password;
     self.screen = Screen::Login;
     self.notify("Logged out", 2);
   }
 
+  // Back to the login screen with the username kept, so only the password has
+  // to be typed again.
+  fn session_expired(&mut self) {
+    let _ = self.rt.block_on(self.api.expire_session());
+    self.secrets.clear();
+    self.reset_password_fields();
+    self.reset_add_fields();
+    self.recovery_words.clear();
+    self.username = self.api.get_current_user().unwrap_or_default().to_string();
+    self.login_step = LoginStep::Password;
+    self.screen = Screen::Login;
+    self.input_mode = InputMode::Editing;
+    self.notify_error("Session expired, log in again");
+  }
+
   pub fn sync_secrets(&mut self) {
     match self.rt.block_on(self.api.sync_and_decrypt()) {
-      Ok(data) => {
-        self.secrets = data;
+      Ok(report) => {
+        self.secrets = report.secrets;
         self.selected = 0;
         self.screen = Screen::Secrets;
-        self.notify("Secrets synced", 2);
+        if !report.issues.is_empty() {
+          self.notify_error(format!(
+            "Vault integrity warning ({} issues): {}",
+            report.issues.len(),
+            report.issues[0]
+          ));
+        } else if report.upgraded.is_empty() {
+          self.notify("Secrets synced", 2);
+        } else {
+          self.notify(
+            format!(
+              "Secrets synced, {} legacy entries upgraded",
+              report.upgraded.len()
+            ),
+            3,
+          );
+        }
       }
-      Err(e) => self.notify(format!("Sync failed: {e}"), 3),
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
+      Err(e) => self.notify(format!("Sync failed: {e:#}"), 3),
     }
   }
 
@@ -212,10 +329,102 @@ impl TuiApp {
     }
 
     let id = self.secrets[self.selected].id.clone();
-    if self.rt.block_on(self.api.delete_secret(id)).is_ok() {
-      self.secrets.remove(self.selected);
-      self.selected = self.selected.saturating_sub(1);
-      self.notify("Secret deleted", 2);
+    match self.rt.block_on(self.api.delete_secret(id)) {
+      Ok(orphaned) => {
+        self.secrets.remove(self.selected);
+        self.selected = self.selected.saturating_sub(1);
+        if orphaned {
+          self.notify_error("Secret deleted; it did not decrypt, so its file chunks were left");
+        } else {
+          self.notify("Secret deleted", 2);
+        }
+      }
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
+      Err(_) => {}
+    }
+  }
+
+  // Saves the selected file entry under ~/Downloads, or the working directory
+  // when there is none. An existing file is never overwritten.
+  pub fn download_selected(&mut self) {
+    let Some(secret) = self.secrets.get(self.selected) else {
+      return;
+    };
+    let SecretPayload::File { name, .. } = secret.payload.expose() else {
+      self.notify_error("Only file entries can be downloaded");
+      return;
+    };
+
+    let dir = home::home_dir()
+      .map(|home| home.join("Downloads"))
+      .filter(|dir| dir.is_dir())
+      .unwrap_or_else(|| PathBuf::from("."));
+    // The stored name came from another machine; keep only its last part.
+    let Some(name) = Path::new(name).file_name() else {
+      self.notify_error("File entry has no usable name");
+      return;
+    };
+    let out = dir.join(name);
+
+    let id = secret.id.clone();
+    match self.rt.block_on(self.api.save_file(&id, &out)) {
+      Ok(_) => self.notify(format!("Saved to {}", out.display()), 3),
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
+      Err(e) => self.notify_error(format!("Download failed: {}", e)),
+    }
+  }
+
+  pub fn generate_recovery_key(&mut self) {
+    match self.rt.block_on(self.api.create_recovery_key()) {
+      Ok(recovery) => {
+        self.recovery_words = recovery.words();
+        self.screen = Screen::RecoveryKey;
+        self.input_mode = InputMode::Normal;
+      }
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
+      Err(e) => self.notify_error(format!("Recovery key failed: {}", e)),
+    }
+  }
+
+  pub fn enter_change_password(&mut self) {
+    self.screen = Screen::ChangePassword;
+    self.input_mode = InputMode::Editing;
+    self.reset_password_fields();
+  }
+
+  fn reset_password_fields(&mut self) {
+    self.password.clear();
+    self.new_password.clear();
+    self.confirm_password.clear();
+    self.password_step = PasswordStep::Current;
+  }
+
+  pub fn change_master_password(&mut self) {
+    if self.new_password.is_empty() {
+      self.notify_error("New password cannot be empty");
+      return;
+    }
+
+    if self.new_password != self.confirm_password {
+      self.notify_error("Passwords do not match");
+      return;
+    }
+
+    let result = self.rt.block_on(
+      self
+        .api
+        .change_master_password(self.password.clone(), self.new_password.clone()),
+    );
+    self.reset_password_fields();
+
+    match result {
+      Ok(_) => {
+        self.screen = Screen::Menu;
+        self.input_mode = InputMode::Normal;
+        self.notify_success("Master password changed");
+      }
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
+      Err(e) => self.notify_error(format!("Password change failed: {}", e)),
     }
   }
 
@@ -233,6 +442,24 @@ impl TuiApp {
     self.add_field = AddField::Title;
   }
 
+  pub fn generate_password(&mut self) {
+    if self.add_kind != AddKind::Password {
+      return;
+    }
+
+    match generator::password(&PasswordPolicy::default()) {
+      Ok(generated) => {
+        self.field2 = generated.value;
+        self.add_field = AddField::Field2;
+        self.notify_info(format!(
+          "Generated a password with {:.0} bits of entropy",
+          generated.entropy
+        ));
+      }
+      Err(e) => self.notify_error(format!("Generate failed: {}", e)),
+    }
+  }
+
   pub fn add_secret(&mut self) {
     if self.title.is_empty() {
       self.notify("Title required", 3);
@@ -242,24 +469,42 @@ impl TuiApp {
     let payload = match self.add_kind {
       AddKind::Password => SecretPayload::Password {
         title: self.title.clone(),
-        login: self.field1.clone(),
-        password: self.field2.clone(),
+        login: self.field1.expose().clone(),
+        password: self.field2.expose().clone(),
         url: if self.field3.is_empty() {
           None
         } else {
-          Some(self.field3.clone())
+          Some(self.field3.expose().clone())
         },
+        fields: Vec::new(),
       },
       AddKind::Note => SecretPayload::Note {
         title: self.title.clone(),
-        content: self.field1.clone(),
+        content: self.field1.expose().clone(),
+        fields: Vec::new(),
       },
       AddKind::Card => SecretPayload::Card {
         title: self.title.clone(),
-        holder: self.field1.clone(),
-        number: self.field2.clone(),
-        expiry: self.field3.clone(),
-        cvv: self.field4.clone(),
+        holder: self.field1.expose().clone(),
+        number: self.field2.expose().clone(),
+        expiry: self.field3.expose().clone(),
+        cvv: self.field4.expose().clone(),
+        fields: Vec::new(),
+      },
+      AddKind::Totp => match TotpSeed::parse(&self.field1) {
+        Ok(seed) => seed.to_payload(self.title.clone()),
+        Err(e) => {
+          self.add_field = AddField::Field1;
+          self.notify_error(format!("{}", e));
+          return;
+        }
+      },
+      AddKind::SshKey => match self.ssh_key_payload() {
+        Ok(payload) => payload,
+        Err(e) => {
+          self.notify_error(format!("{}", e));
+          return;
+        }
       },
     };
 
@@ -269,10 +514,27 @@ impl TuiApp {
         self.input_mode = InputMode::Normal;
         self.notify("Secret added", 2);
       }
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
       Err(e) => self.notify(format!("Add failed: {e}"), 3),
     }
   }
 
+  // Imports the key file named in Field1, or generates a key when it is empty.
+  fn ssh_key_payload(&self) -> Result<SecretPayload> {
+    let optional = |field: &SecretString| Some(field.expose().clone()).filter(|v| !v.is_empty());
+    let passphrase = optional(&self.field2);
+    let comment = optional(&self.field3);
+
+    if self.field1.is_empty() {
+      return sshkey::generate(self.title.clone(), passphrase, comment);
+    }
+    let path = self.field1.expose().trim();
+    let private_key = std::fs::read_to_string(path)
+      .map(SecretString::new)
+      .map_err(|e| anyhow!("Cannot read {}: {}", path, e))?;
+    sshkey::import(self.title.clone(), &private_key, passphrase, comment)
+  }
+
   pub fn next(&mut self) {
     if !self.secrets.is_empty() {
       self.selected = (self.selected + 1).min(self.secrets.len() - 1);
@@ -294,7 +556,15 @@ impl TuiApp {
         LoginStep::Password => self.password.push(c),
         LoginStep::ConfirmPassword => self.confirm_password.push(c),
       },
-      Screen::MasterPassword => self.password.push(c),
+      Screen::MasterPassword => match self.master_field {
+        MasterField::Password => self.password.push(c),
+        MasterField::Keyfile => self.keyfile_path.push(c),
+      },
+      Screen::ChangePassword => match self.password_step {
+        PasswordStep::Current => self.password.push(c),
+        PasswordStep::New => self.new_password.push(c),
+        PasswordStep::Confirm => self.confirm_password.push(c),
+      },
       Screen::AddSecret => match self.add_field {
         AddField::Kind => {}
         AddField::Title => self.title.push(c),
@@ -320,9 +590,25 @@ impl TuiApp {
           self.confirm_password.pop();
         }
       },
-      Screen::MasterPassword => {
-        self.password.pop();
-      }
+      Screen::MasterPassword => match self.master_field {
+        MasterField::Password => {
+          self.password.pop();
+        }
+        MasterField::Keyfile => {
+          self.keyfile_path.pop();
+        }
+      },
+      Screen::ChangePassword => match self.password_step {
+        PasswordStep::Current => {
+          self.password.pop();
+        }
+        PasswordStep::New => {
+          self.new_password.pop();
+        }
+        PasswordStep::Confirm => {
+          self.confirm_password.pop();
+        }
+      },
       Screen::AddSecret => match self.add_field {
         AddField::Kind => {}
         AddField::Title => {
@@ -360,6 +646,11 @@ impl TuiApp {
       Screen::MasterPassword => {
         self.submit_master_password();
       }
+      Screen::ChangePassword => match self.password_step {
+        PasswordStep::Current => self.password_step = PasswordStep::New,
+        PasswordStep::New => self.password_step = PasswordStep::Confirm,
+        PasswordStep::Confirm => self.change_master_password(),
+      },
       Screen::AddSecret => self.add_secret(),
       _ => {}
     }
@@ -369,14 +660,16 @@ impl TuiApp {
     match self.screen {
       Screen::AddSecret => {
         let field_order: Vec<AddField> = match self.add_kind {
-          AddKind::Password => vec![
+          AddKind::Password | AddKind::SshKey => vec![
             AddField::Kind,
             AddField::Title,
             AddField::Field1,
             AddField::Field2,
             AddField::Field3,
           ],
-          AddKind::Note => vec![AddField::Kind, AddField::Title, AddField::Field1],
+          AddKind::Note | AddKind::Totp => {
+            vec![AddField::Kind, AddField::Title, AddField::Field1]
+          }
           AddKind::Card => vec![
             AddField::Kind,
             AddField::Title,
@@ -409,6 +702,21 @@ impl TuiApp {
         };
       }
 
+      Screen::MasterPassword => {
+        self.master_field = match self.master_field {
+          MasterField::Password => MasterField::Keyfile,
+          MasterField::Keyfile => MasterField::Password,
+        };
+      }
+
+      Screen::ChangePassword => {
+        self.password_step = match self.password_step {
+          PasswordStep::Current => PasswordStep::New,
+          PasswordStep::New => PasswordStep::Confirm,
+          PasswordStep::Confirm => PasswordStep::Current,
+        };
+      }
+
       _ => {}
     }
   }
@@ -419,39 +727,75 @@ impl TuiApp {
       return;
     }
 
-    self.api.set_master_password(self.password.clone());
+    if self.keyfile_path.is_empty() {
+      self.api.set_keyfile(None);
+    } else {
+      match Keyfile::load(Path::new(&self.keyfile_path)) {
+        Ok(keyfile) => self.api.set_keyfile(Some(keyfile)),
+        Err(e) => {
+          self.master_field = MasterField::Keyfile;
+          self.notify_error(format!("{}", e));
+          return;
+        }
+      }
+    }
+
+    let result = self.rt.block_on(self.api.unlock(self.password.clone()));
     self.password.clear();
 
-    self.screen = Screen::Menu;
-    self.input_mode = InputMode::Normal;
+    match result {
+      Ok(_) => {
+        self.screen = Screen::Menu;
+        self.input_mode = InputMode::Normal;
 
-    self.notify_success("Master password set");
+        self.notify_success("Vault unlocked");
+      }
+      Err(e) if e.is::<WrongPassword>() => {
+        self.input_mode = InputMode::Editing;
+        self.master_field = MasterField::Password;
+        if self.api.requires_keyfile() {
+          self.notify_error("Wrong master password or keyfile, try again");
+        } else {
+          self.notify_error("Wrong master password, try again");
+        }
+      }
+      Err(e) if e.is::<KeyfileRequired>() => self.ask_for_keyfile(),
+      Err(e) if e.is::<SessionExpired>() => self.session_expired(),
+      Err(e) => self.notify_error(format!("Unlock failed: {}", e)),
+    }
   }
 
-  pub fn current_secret_fields(&self) -> Vec<(String, String)> {
+  fn ask_for_keyfile(&mut self) {
+    self.screen = Screen::MasterPassword;
+    self.input_mode = InputMode::Editing;
+    self.master_field = MasterField::Keyfile;
+    self.notify_error("This vault needs its keyfile; enter its path");
+  }
+
+  // Values stay in wiped buffers: the detail view asks for them on every draw.
+  // The kind tells it which ones to mask.
+  pub fn current_secret_fields(&self) -> Vec<(String, SecretString, FieldKind)> {
     if let Some(secret) = self.secrets.get(self.selected) {
-      match &secret.payload {
+      let mut fields = match secret.payload.expose() {
         SecretPayload::Password {
           title,
           login,
           password,
           url,
+          ..
         } => {
           let mut fields = vec![
-            ("Title".into(), title.clone()),
-            ("Login".into(), login.clone()),
-            ("Password".into(), password.clone()),
+            field("Title", title),
+            field("Login", login),
+            field("Password", password),
           ];
           if let Some(u) = url {
-            fields.push(("URL".into(), u.clone()));
+            fields.push(field("URL", u));
           }
           fields
         }
-        SecretPayload::Note { title, content } => {
-          vec![
-            ("Title".into(), title.clone()),
-            ("Content".into(), content.clone()),
-          ]
+        SecretPayload::Note { title, content, .. } => {
+          vec![field("Title", title), field("Content", content)]
         }
         SecretPayload::Card {
           title,
@@ -459,18 +803,94 @@ impl TuiApp {
           number,
           expiry,
           cvv,
+          ..
         } => {
           vec![
-            ("Title".into(), title.clone()),
-            ("Holder".into(), holder.clone()),
-            ("Number".into(), number.clone()),
-            ("Expiry".into(), expiry.clone()),
-            ("CVV".into(), cvv.clone()),
+            field("Title", title),
+            field("Holder", holder),
+            field("Number", number),
+            field("Expiry", expiry),
+            field("CVV", cvv),
           ]
         }
-      }
+        SecretPayload::Totp {
+          title,
+          issuer,
+          account,
+          ..
+        } => {
+          let mut fields = vec![field("Title", title)];
+          if let Some(issuer) = issuer {
+            fields.push(field("Issuer", issuer));
+          }
+          if let Some(account) = account {
+            fields.push(field("Account", account));
+          }
+          match totp::current_code(secret.payload.expose()) {
+            Ok(current) => fields.push(field("Code", &current.code)),
+            Err(e) => fields.push(field("Code", &e.to_string())),
+          }
+          fields
+        }
+        SecretPayload::SshKey {
+          title,
+          private_key,
+          passphrase,
+          public_key,
+          comment,
+          ..
+        } => {
+          let fingerprint = match sshkey::fingerprint(public_key) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => format!("{}", e),
+          };
+          let mut fields = vec![
+            field("Title", title),
+            field("Comment", comment),
+            field("Fingerprint", &fingerprint),
+            field("Public key", public_key),
+            field("Private key", private_key),
+          ];
+          if let Some(passphrase) = passphrase {
+            fields.push(field("Passphrase", passphrase));
+          }
+          fields
+        }
+        SecretPayload::File {
+          title, name, size, ..
+        } => {
+          vec![
+            field("Title", title),
+            field("Name", name),
+            field("Size", &format!("{} bytes", size)),
+          ]
+        }
+      };
+      fields.extend(
+        secret
+          .payload
+          .expose()
+          .custom_fields()
+          .iter()
+          .map(|custom| {
+            (
+              custom.name.clone(),
+              SecretString::new(custom.value.clone()),
+              custom.kind,
+            )
+          }),
+      );
+      fields
     } else {
       vec![]
     }
   }
 }
+
+fn field(label: &str, value: &str) -> (String, SecretString, FieldKind) {
+  (
+    label.to_string(),
+    SecretString::new(value.to_string()),
+    FieldKind::Visible,
+  )
+}
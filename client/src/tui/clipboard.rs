@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-pub fn copy(text: String) -> Result<()> {
+pub fn copy(text: &str) -> Result<()> {
   let mut clipboard = arboard::Clipboard::new()?;
   clipboard.set_text(text)?;
   Ok(())
@@ -24,6 +24,14 @@ pub fn handle_key(app: &mut TuiApp, key: KeyEvent) {
         app.input_mode = InputMode::Editing;
         return;
       }
+      KeyCode::Char('a') if app.screen == Screen::Login => {
+        app.migrate_account();
+        return;
+      }
+      KeyCode::Char('g') if app.screen == Screen::AddSecret => {
+        app.generate_password();
+        return;
+      }
       _ => {}
     }
   }
@@ -39,9 +47,12 @@ fn handle_normal(app: &mut TuiApp, key: KeyEvent) {
     KeyCode::Char('l') if app.screen != Screen::MasterPassword => app.logout(),
     KeyCode::Esc => {
       app.input_mode = InputMode::Normal;
+      app.recovery_words.clear();
 
       app.screen = match app.screen {
-        Screen::Secrets | Screen::AddSecret => Screen::Menu,
+        Screen::Secrets | Screen::AddSecret | Screen::ChangePassword | Screen::RecoveryKey => {
+          Screen::Menu
+        }
         Screen::Register => Screen::Register,
         Screen::Login => Screen::Login,
         Screen::Menu => Screen::Menu,
@@ -59,25 +70,24 @@ fn handle_normal(app: &mut TuiApp, key: KeyEvent) {
     KeyCode::Char('d') if app.screen == Screen::Secrets => {
       app.delete_selected();
     }
+    KeyCode::Char('w') if app.screen == Screen::Secrets => app.download_selected(),
 
     KeyCode::Up if app.screen == Screen::Menu => {
-      if app.selected > 0 {
-        app.selected -= 1;
-      }
+      app.selected = app.selected.saturating_sub(1);
     }
 
     KeyCode::Down if app.screen == Screen::Menu => {
-      if app.selected < 4 {
-        app.selected += 1;
-      }
+      app.selected = (app.selected + 1).min(6);
     }
 
     KeyCode::Enter if app.screen == Screen::Menu => match app.selected {
       0 => app.sync_secrets(),
       1 => app.enter_add_secret(),
       2 => app.sync_secrets(),
-      3 => app.logout(),
-      4 => app.should_quit = true,
+      3 => app.enter_change_password(),
+      4 => app.generate_recovery_key(),
+      5 => app.logout(),
+      6 => app.should_quit = true,
       _ => {}
     },
 
@@ -108,23 +118,23 @@ fn handle_editing(app: &mut TuiApp, key: KeyEvent) {
     KeyCode::Enter => app.submit(),
     KeyCode::Backspace => app.backspace(),
     KeyCode::Char(c) => app.push_char(c),
-    KeyCode::Left => {
-      if app.screen == Screen::AddSecret && app.add_field == AddField::Kind {
-        app.add_kind = match app.add_kind {
-          AddKind::Password => AddKind::Card,
-          AddKind::Note => AddKind::Password,
-          AddKind::Card => AddKind::Note,
-        };
-      }
+    KeyCode::Left if app.screen == Screen::AddSecret && app.add_field == AddField::Kind => {
+      app.add_kind = match app.add_kind {
+        AddKind::Password => AddKind::SshKey,
+        AddKind::Note => AddKind::Password,
+        AddKind::Card => AddKind::Note,
+        AddKind::Totp => AddKind::Card,
+        AddKind::SshKey => AddKind::Totp,
+      };
     }
-    KeyCode::Right => {
-      if app.screen == Screen::AddSecret && app.add_field == AddField::Kind {
-        app.add_kind = match app.add_kind {
-          AddKind::Password => AddKind::Note,
-          AddKind::Note => AddKind::Card,
-          AddKind::Card => AddKind::Password,
-        };
-      }
+    KeyCode::Right if app.screen == Screen::AddSecret && app.add_field == AddField::Kind => {
+      app.add_kind = match app.add_kind {
+        AddKind::Password => AddKind::Note,
+        AddKind::Note => AddKind::Card,
+        AddKind::Card => AddKind::Totp,
+        AddKind::Totp => AddKind::SshKey,
+        AddKind::SshKey => AddKind::Password,
+      };
     }
     _ => {}
   }
@@ -138,9 +148,9 @@ fn copy_to_clipboard(app: &mut TuiApp) {
     return;
   }
 
-  let (_, value) = &fields[app.detail_selected];
+  let (_, value, _) = &fields[app.detail_selected];
 
-  match copy(value.clone()) {
+  match copy(value) {
     Ok(_) => app.notify_success("Copied to clipboard"),
     Err(e) => app.notify_error(format!("Clipboard error: {}", e)),
   }
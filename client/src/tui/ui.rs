@@ -1,4 +1,6 @@
-use crate::tui::app::{AddField, AddKind, InputMode, LoginStep, Screen, TuiApp};
+use crate::tui::app::{
+  AddField, AddKind, InputMode, LoginStep, MasterField, PasswordStep, Screen, TuiApp,
+};
 use ratatui::{
   Frame,
   layout::{Alignment, Constraint, Direction, Layout, Rect},
@@ -7,7 +9,9 @@ use ratatui::{
   widgets::{Block, BorderType, Borders, List, ListItem, Paragraph, Row, Table, Wrap},
 };
 
-use crate::core::models::SecretPayload;
+use crate::core::format_remaining;
+use crate::core::models::{FieldKind, SecretPayload};
+use crate::core::totp;
 
 pub fn draw(f: &mut Frame, app: &TuiApp) {
   let chunks = Layout::default()
@@ -54,6 +58,8 @@ fn draw_body(f: &mut Frame, app: &TuiApp, area: Rect) {
     Screen::AddSecret => draw_add_secret(f, app, area),
     Screen::Login | Screen::Register => draw_auth(f, app, area),
     Screen::MasterPassword => draw_master_password(f, app),
+    Screen::ChangePassword => draw_change_password(f, app, area),
+    Screen::RecoveryKey => draw_recovery_key(f, app, area),
   }
 }
 
@@ -105,6 +111,8 @@ fn draw_menu_list(f: &mut Frame, app: &TuiApp, area: Rect) {
     "Sync secrets",
     "Add secret",
     "View secrets",
+    "Change master password",
+    "Generate recovery key",
     "Logout",
     "Quit",
   ];
@@ -144,12 +152,21 @@ fn draw_info_panel(f: &mut Frame, app: &TuiApp, area: Rect) {
     .add_modifier(Modifier::BOLD);
 
   let user = app.api.get_current_user().unwrap_or("Not logged in");
+  let session = match app.api.session_remaining() {
+    Some(remaining) if remaining.is_zero() => "expired".to_string(),
+    Some(remaining) => format!("{} left", format_remaining(remaining)),
+    None => "none".to_string(),
+  };
 
   let lines = vec![
     Line::from(vec![
       Span::styled("User: ", label),
       Span::styled(user, Style::default().fg(Color::Cyan)),
     ]),
+    Line::from(vec![
+      Span::styled("Session: ", label),
+      Span::styled(session, Style::default().fg(Color::Cyan)),
+    ]),
     Line::from(vec![
       Span::styled("Secrets: ", label),
       Span::styled(
@@ -197,6 +214,26 @@ fn draw_info_panel(f: &mut Frame, app: &TuiApp, area: Rect) {
   f.render_widget(panel, area);
 }
 
+// The share of the TOTP period left before the code changes.
+fn countdown_line(remaining: u64, period: u64) -> Line<'static> {
+  const WIDTH: u64 = 20;
+  let filled = (remaining * WIDTH).div_ceil(period.max(1)) as usize;
+  let color = if remaining <= 5 {
+    Color::LightRed
+  } else {
+    Color::LightGreen
+  };
+  Line::from(vec![
+    Span::styled("Expires: ", Style::default().fg(Color::DarkGray)),
+    Span::styled("█".repeat(filled), Style::default().fg(color)),
+    Span::styled(
+      "░".repeat(WIDTH as usize - filled),
+      Style::default().fg(Color::DarkGray),
+    ),
+    Span::raw(format!(" {}s", remaining)),
+  ])
+}
+
 pub fn draw_secrets(f: &mut Frame, app: &TuiApp, area: Rect) {
   let chunks = Layout::default()
     .direction(Direction::Vertical)
@@ -220,16 +257,22 @@ pub fn draw_secrets(f: &mut Frame, app: &TuiApp, area: Rect) {
     .iter()
     .enumerate()
     .map(|(i, s)| {
-      let kind = match &s.payload {
+      let kind = match s.payload.expose() {
         SecretPayload::Password { .. } => "Password",
         SecretPayload::Note { .. } => "Note",
         SecretPayload::Card { .. } => "Card",
+        SecretPayload::Totp { .. } => "TOTP",
+        SecretPayload::SshKey { .. } => "SSH key",
+        SecretPayload::File { .. } => "File",
       };
 
-      let title = match &s.payload {
+      let title = match s.payload.expose() {
         SecretPayload::Password { title, .. } => title,
         SecretPayload::Note { title, .. } => title,
         SecretPayload::Card { title, .. } => title,
+        SecretPayload::Totp { title, .. } => title,
+        SecretPayload::SshKey { title, .. } => title,
+        SecretPayload::File { title, .. } => title,
       };
 
       let style = if i == app.selected {
@@ -270,7 +313,7 @@ pub fn draw_secrets(f: &mut Frame, app: &TuiApp, area: Rect) {
 
     let fields = app.current_secret_fields();
 
-    for (i, (label, value)) in fields.iter().enumerate() {
+    for (i, (label, value, kind)) in fields.iter().enumerate() {
       let style = if i == app.detail_selected {
         Style::default()
           .fg(Color::Black)
@@ -282,10 +325,22 @@ pub fn draw_secrets(f: &mut Frame, app: &TuiApp, area: Rect) {
 
       lines.push(Line::from(vec![
         Span::styled(format!("{}: ", label), Style::default().fg(Color::DarkGray)),
-        Span::styled(value.clone(), style),
+        // Hidden values stay copyable, but are not put on screen.
+        Span::styled(
+          if *kind == FieldKind::Hidden {
+            "********"
+          } else {
+            value.as_str()
+          },
+          style,
+        ),
       ]));
     }
 
+    if let Ok(current) = totp::current_code(secret.payload.expose()) {
+      lines.push(countdown_line(current.remaining, current.period));
+    }
+
     lines.push(Line::from(""));
     lines.push(Line::from(vec![
       Span::styled("Created: ", label_style),
@@ -339,6 +394,13 @@ let help = Line::from(vec![
             .add_modifier(Modifier::BOLD),
     ),
     Span::raw("Delete • "),
+    Span::styled(
+        "w ",
+        Style::default()
+            .fg(Color::LightYellow)
+            .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw("Download • "),
     Span::styled(
         "ESC ",
         Style::default()
@@ -418,11 +480,13 @@ pub fn draw_add_secret(f: &mut Frame, app: &TuiApp, area: Rect) {
   let mut field_widgets = Vec::new();
 
   field_widgets.push(make_field(
-    "Kind: Password/Note/Card",
+    "Kind: Password/Note/Card/TOTP/SSH key",
     match app.add_kind {
       AddKind::Password => "Password",
       AddKind::Note => "Note",
       AddKind::Card => "Card",
+      AddKind::Totp => "TOTP",
+      AddKind::SshKey => "SSH key",
     },
     AddField::Kind,
   ));
@@ -445,6 +509,24 @@ pub fn draw_add_secret(f: &mut Frame, app: &TuiApp, area: Rect) {
       field_widgets.push(make_field("Expiry", &app.field3, AddField::Field3));
       field_widgets.push(make_field("CVV", &app.field4, AddField::Field4));
     }
+    AddKind::Totp => {
+      field_widgets.push(make_field("Title", &app.title, AddField::Title));
+      field_widgets.push(make_field(
+        "Base32 secret or otpauth:// URI",
+        &app.field1,
+        AddField::Field1,
+      ));
+    }
+    AddKind::SshKey => {
+      field_widgets.push(make_field("Title", &app.title, AddField::Title));
+      field_widgets.push(make_field(
+        "Private key file, empty to generate ed25519",
+        &app.field1,
+        AddField::Field1,
+      ));
+      field_widgets.push(make_field("Passphrase", &app.field2, AddField::Field2));
+      field_widgets.push(make_field("Comment", &app.field3, AddField::Field3));
+    }
   }
 
   let field_count = field_widgets.len() as u16;
@@ -471,23 +553,34 @@ pub fn draw_add_secret(f: &mut Frame, app: &TuiApp, area: Rect) {
     f.render_widget(widget, rect);
   }
 
-  let help = Paragraph::new(Line::from(vec![
+  let mut help_spans = vec![
     Span::styled("TAB ", Style::default().add_modifier(Modifier::BOLD)),
     Span::raw("Next field • "),
     Span::styled("←/→ ", Style::default().add_modifier(Modifier::BOLD)),
     Span::raw("Change Kind • "),
+  ];
+  if app.add_kind == AddKind::Password {
+    help_spans.push(Span::styled(
+      "Ctrl+g ",
+      Style::default().add_modifier(Modifier::BOLD),
+    ));
+    help_spans.push(Span::raw("Generate password • "));
+  }
+  help_spans.extend([
     Span::styled("ENTER ", Style::default().add_modifier(Modifier::BOLD)),
     Span::raw("Save • "),
     Span::styled("ESC ", Style::default().add_modifier(Modifier::BOLD)),
     Span::raw("Cancel"),
-  ]))
-  .block(
-    Block::default()
-      .borders(Borders::ALL)
-      .border_type(BorderType::Rounded),
-  )
-  .alignment(ratatui::layout::Alignment::Center)
-  .style(Style::default().fg(Color::Gray));
+  ]);
+
+  let help = Paragraph::new(Line::from(help_spans))
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded),
+    )
+    .alignment(ratatui::layout::Alignment::Center)
+    .style(Style::default().fg(Color::Gray));
 
   f.render_widget(help, chunks[1]);
 }
@@ -664,6 +757,13 @@ fn draw_auth(f: &mut Frame, app: &TuiApp, area: Rect) {
         .add_modifier(Modifier::BOLD),
     ),
     Span::raw(" login • "),
+    Span::styled(
+      "Ctrl+a",
+      Style::default()
+        .fg(Color::LightYellow)
+        .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw(" migrate • "),
     Span::styled(
       "Tab",
       Style::default()
@@ -741,7 +841,7 @@ pub fn draw_master_password(f: &mut Frame, app: &TuiApp) {
 
   let compact_area = Layout::default()
     .direction(Direction::Vertical)
-    .constraints([Constraint::Length(10), Constraint::Min(0)])
+    .constraints([Constraint::Length(13), Constraint::Min(0)])
     .split(size);
 
   let centered_area = Layout::default()
@@ -757,7 +857,11 @@ pub fn draw_master_password(f: &mut Frame, app: &TuiApp) {
 
   let chunks = Layout::default()
     .direction(Direction::Vertical)
-    .constraints([Constraint::Length(5), Constraint::Length(4)])
+    .constraints([
+      Constraint::Length(5),
+      Constraint::Length(4),
+      Constraint::Length(3),
+    ])
     .split(area);
 
   let info_text = vec![
@@ -843,6 +947,13 @@ pub fn draw_master_password(f: &mut Frame, app: &TuiApp) {
           .add_modifier(Modifier::BOLD),
       ),
       Span::styled(": confirm  ", Style::default().fg(Color::White)),
+      Span::styled(
+        "Tab",
+        Style::default()
+          .fg(Color::LightYellow)
+          .add_modifier(Modifier::BOLD),
+      ),
+      Span::styled(": keyfile  ", Style::default().fg(Color::White)),
       Span::styled(
         "Esc",
         Style::default()
@@ -867,6 +978,9 @@ pub fn draw_master_password(f: &mut Frame, app: &TuiApp) {
     masked
   };
 
+  let editing_password =
+    app.input_mode == InputMode::Editing && app.master_field == MasterField::Password;
+
   let input = if app.password.is_empty() && app.input_mode == InputMode::Normal {
     Paragraph::new(display_password)
       .style(Style::default().fg(Color::DarkGray))
@@ -876,19 +990,207 @@ pub fn draw_master_password(f: &mut Frame, app: &TuiApp) {
           .border_style(Style::default().fg(Color::DarkGray)),
       )
   } else {
+    let color = if editing_password {
+      Color::Yellow
+    } else {
+      Color::DarkGray
+    };
     Paragraph::new(display_password)
       .style(Style::default().fg(Color::Yellow))
       .block(
         Block::default()
           .borders(Borders::ALL)
-          .border_style(Style::default().fg(Color::Yellow)),
+          .border_style(Style::default().fg(color)),
       )
   };
 
   f.render_widget(input, password_section[1]);
-  if app.input_mode == InputMode::Editing {
+
+  let editing_keyfile =
+    app.input_mode == InputMode::Editing && app.master_field == MasterField::Keyfile;
+  let keyfile_title = if app.api.requires_keyfile() {
+    " Keyfile (required) "
+  } else {
+    " Keyfile (optional) "
+  };
+
+  let keyfile = Paragraph::new(app.keyfile_path.as_str())
+    .style(Style::default().fg(Color::White))
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .title(keyfile_title)
+        .border_style(if editing_keyfile {
+          Style::default().fg(Color::Yellow)
+        } else {
+          Style::default().fg(Color::DarkGray)
+        }),
+    );
+
+  f.render_widget(keyfile, chunks[2]);
+
+  if editing_password {
     let cursor_x = password_section[1].x + 1 + app.password.len() as u16;
     let cursor_y = password_section[1].y + 1;
     f.set_cursor(cursor_x, cursor_y);
+  } else if editing_keyfile {
+    let cursor_x = chunks[2].x + 1 + app.keyfile_path.chars().count() as u16;
+    let cursor_y = chunks[2].y + 1;
+    f.set_cursor(cursor_x, cursor_y);
   }
 }
+
+fn draw_change_password(f: &mut Frame, app: &TuiApp, area: Rect) {
+  let centered_area = Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([
+      Constraint::Percentage(30),
+      Constraint::Percentage(40),
+      Constraint::Percentage(30),
+    ])
+    .split(area);
+
+  let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Min(5), Constraint::Length(3)])
+    .split(centered_area[1]);
+
+  let masked_box = |title: &str, value: &str, step: PasswordStep| {
+    let active = app.password_step == step;
+    let mask = "*".repeat(value.len());
+
+    Paragraph::new(if active { format!("{}█", mask) } else { mask })
+      .style(if active {
+        Style::default().fg(Color::LightYellow)
+      } else {
+        Style::default().fg(Color::White)
+      })
+      .block(
+        Block::default()
+          .borders(Borders::ALL)
+          .border_style(if active {
+            Style::default().fg(Color::LightYellow)
+          } else {
+            Style::default().fg(Color::DarkGray)
+          })
+          .title(format!(" {} ", title))
+          .title_alignment(Alignment::Center),
+      )
+  };
+
+  let input_boxes = [
+    masked_box("Current password", &app.password, PasswordStep::Current),
+    masked_box("New password", &app.new_password, PasswordStep::New),
+    masked_box("Confirm", &app.confirm_password, PasswordStep::Confirm),
+  ];
+
+  let box_height = 3;
+  let spacing = 1;
+  let total_height =
+    (box_height * input_boxes.len() as u16) + (spacing * (input_boxes.len() as u16 - 1));
+
+  let box_layout = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints(
+      std::iter::once(Constraint::Length(
+        chunks[0].height.saturating_sub(total_height) / 2,
+      ))
+      .chain(
+        input_boxes
+          .iter()
+          .flat_map(|_| [Constraint::Length(box_height), Constraint::Length(spacing)]),
+      )
+      .take(input_boxes.len() * 2 + 1)
+      .collect::<Vec<_>>(),
+    )
+    .split(chunks[0]);
+
+  for (i, box_widget) in input_boxes.into_iter().enumerate() {
+    f.render_widget(box_widget, box_layout[i * 2 + 1]);
+  }
+
+  let help_text = Line::from(vec![
+    Span::styled(
+      "Tab",
+      Style::default()
+        .fg(Color::LightYellow)
+        .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw(" next • "),
+    Span::styled(
+      "Enter",
+      Style::default()
+        .fg(Color::LightGreen)
+        .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw(" submit • "),
+    Span::styled(
+      "Esc",
+      Style::default()
+        .fg(Color::LightYellow)
+        .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw(" back"),
+  ]);
+
+  let help_widget = Paragraph::new(help_text)
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::White));
+
+  f.render_widget(help_widget, chunks[1]);
+}
+
+fn draw_recovery_key(f: &mut Frame, app: &TuiApp, area: Rect) {
+  let centered_area = Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([
+      Constraint::Percentage(20),
+      Constraint::Percentage(60),
+      Constraint::Percentage(20),
+    ])
+    .split(area);
+
+  let words: Vec<&str> = app.recovery_words.split_whitespace().collect();
+
+  let mut lines = vec![
+    Line::from(Span::styled(
+      "Write these words down and keep them offline",
+      Style::default()
+        .fg(Color::LightYellow)
+        .add_modifier(Modifier::BOLD),
+    )),
+    Line::from(""),
+  ];
+
+  for (row, chunk) in words.chunks(4).enumerate() {
+    let cells: Vec<Span> = chunk
+      .iter()
+      .enumerate()
+      .map(|(col, word)| Span::raw(format!("{:>2}. {:<10} ", row * 4 + col + 1, word)))
+      .collect();
+    lines.push(Line::from(cells));
+  }
+
+  lines.push(Line::from(""));
+  lines.push(Line::from(
+    "They open the vault without the master password. Recover with `gk-cli account recover`.",
+  ));
+  lines.push(Line::from(Span::styled(
+    "Esc returns to the menu and clears them from the screen",
+    Style::default().fg(Color::DarkGray),
+  )));
+
+  let kit = Paragraph::new(lines)
+    .alignment(Alignment::Center)
+    .wrap(Wrap { trim: true })
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .title(" Emergency kit ")
+        .title_alignment(Alignment::Center)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(Color::LightYellow)),
+    );
+
+  f.render_widget(kit, centered_area[1]);
+}
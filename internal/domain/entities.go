@@ -21,6 +21,21 @@ type AuthRequest struct {
 	Password string `json:"password"`
 }
 
+type ChangePasswordRequest struct {
+	Password    string `json:"password"`
+	NewPassword string `json:"new_password"`
+}
+
+type RecoveryRequest struct {
+	Recovery string `json:"recovery"`
+}
+
+type RecoverRequest struct {
+	Login       string `json:"login"`
+	Recovery    string `json:"recovery"`
+	NewPassword string `json:"new_password"`
+}
+
 type Token struct {
 	JWT string `json:"token"`
 }
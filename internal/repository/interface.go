@@ -16,11 +16,15 @@ type Storage interface {
 type UserRepository interface {
 	CreateUser(ctx context.Context, login, hash string) error
 	GetUser(ctx context.Context, login string) (string, error)
+	UpdateUser(ctx context.Context, login, hash string) error
+	GetRecovery(ctx context.Context, login string) (string, error)
+	UpdateRecovery(ctx context.Context, login, hash string) error
 }
 
 type DataRepository interface {
 	AddData(ctx context.Context, login string, secret domain.Secret) error
 	SaveSecrets(ctx context.Context, login string, secrets []domain.Secret) error
-	GetData(ctx context.Context, login string) ([]domain.Secret, error)
+	GetData(ctx context.Context, login, skipType string) ([]domain.Secret, error)
+	GetSecret(ctx context.Context, login, id string) (domain.Secret, error)
 	DeleteData(ctx context.Context, login, id string) error
 }
@@ -11,12 +11,14 @@ import (
 type MemoryStorage struct {
 	mu          sync.RWMutex
 	users       map[string]string
+	recovery    map[string]string
 	userSecrets map[string][]domain.Secret
 }
 
 func NewMemoryStorage() *MemoryStorage {
 	return &MemoryStorage{
 		users:       make(map[string]string),
+		recovery:    make(map[string]string),
 		userSecrets: make(map[string][]domain.Secret),
 	}
 }
@@ -61,6 +63,41 @@ func (s *MemoryStorage) GetUser(ctx context.Context, login string) (string, erro
 	return hash, nil
 }
 
+func (s *MemoryStorage) UpdateUser(ctx context.Context, login, hash string) error {
+	s.mu.Lock()
+	defer s.mu.Unlock()
+
+	if _, ok := s.users[login]; !ok {
+		return domain.ErrInvalidCreds
+	}
+
+	s.users[login] = hash
+	return nil
+}
+
+func (s *MemoryStorage) GetRecovery(ctx context.Context, login string) (string, error) {
+	s.mu.RLock()
+	defer s.mu.RUnlock()
+
+	if _, ok := s.users[login]; !ok {
+		return "", domain.ErrInvalidCreds
+	}
+
+	return s.recovery[login], nil
+}
+
+func (s *MemoryStorage) UpdateRecovery(ctx context.Context, login, hash string) error {
+	s.mu.Lock()
+	defer s.mu.Unlock()
+
+	if _, ok := s.users[login]; !ok {
+		return domain.ErrInvalidCreds
+	}
+
+	s.recovery[login] = hash
+	return nil
+}
+
 func (s *MemoryStorage) SaveSecrets(ctx context.Context, login string, secrets []domain.Secret) error {
 	if len(secrets) == 0 {
 		return nil
@@ -104,18 +141,35 @@ func (s *MemoryStorage) AddData(ctx context.Context, login string, secret domain
 	return s.SaveSecrets(ctx, login, []domain.Secret{secret})
 }
 
-func (s *MemoryStorage) GetData(ctx context.Context, login string) ([]domain.Secret, error) {
+func (s *MemoryStorage) GetData(ctx context.Context, login, skipType string) ([]domain.Secret, error) {
 	s.mu.RLock()
 	defer s.mu.RUnlock()
 
 	items := s.userSecrets[login]
-	result := make([]domain.Secret, len(items))
-	copy(result, items)
+	result := make([]domain.Secret, 0, len(items))
+	for _, v := range items {
+		if skipType == "" || v.Type != skipType {
+			result = append(result, v)
+		}
+	}
 
 	return result, nil
 
 }
 
+func (s *MemoryStorage) GetSecret(ctx context.Context, login, id string) (domain.Secret, error) {
+	s.mu.RLock()
+	defer s.mu.RUnlock()
+
+	for _, v := range s.userSecrets[login] {
+		if v.ID == id {
+			return v, nil
+		}
+	}
+
+	return domain.Secret{}, domain.ErrSecretNotFound
+}
+
 func (s *MemoryStorage) DeleteData(ctx context.Context, login, id string) error {
 	s.mu.Lock()
 	defer s.mu.Unlock()
@@ -2,6 +2,7 @@ package repository
 
 import (
 	"context"
+	"errors"
 	"time"
 
 	"github.com/Doctor46-create/gophkeeper/internal/config"
@@ -106,6 +107,51 @@ func (s *PostgresStorage) GetUser(
 	return hash, nil
 }
 
+func (s *PostgresStorage) UpdateUser(
+	ctx context.Context,
+	login, hash string,
+) error {
+	tag, err := s.exec(ctx, userUpdate, login, hash)
+	if err != nil {
+		return HandleDBError(err)
+	}
+
+	if tag.RowsAffected() == 0 {
+		return domain.ErrInvalidCreds
+	}
+
+	return nil
+}
+
+func (s *PostgresStorage) GetRecovery(
+	ctx context.Context,
+	login string,
+) (string, error) {
+	var hash string
+	err := s.pool.QueryRow(ctx, recoveryGet, login).Scan(&hash)
+	if err != nil {
+		return "", HandleDBError(err)
+	}
+
+	return hash, nil
+}
+
+func (s *PostgresStorage) UpdateRecovery(
+	ctx context.Context,
+	login, hash string,
+) error {
+	tag, err := s.exec(ctx, recoveryUpdate, login, hash)
+	if err != nil {
+		return HandleDBError(err)
+	}
+
+	if tag.RowsAffected() == 0 {
+		return domain.ErrInvalidCreds
+	}
+
+	return nil
+}
+
 func (s *PostgresStorage) SaveSecrets(
 	ctx context.Context,
 	login string,
@@ -163,9 +209,9 @@ func (s *PostgresStorage) AddData(
 
 func (s *PostgresStorage) GetData(
 	ctx context.Context,
-	login string,
+	login, skipType string,
 ) ([]domain.Secret, error) {
-	rows, err := s.pool.Query(ctx, secretGet, login)
+	rows, err := s.pool.Query(ctx, secretGet, login, skipType)
 	if err != nil {
 		return nil, err
 	}
@@ -185,6 +231,29 @@ func (s *PostgresStorage) GetData(
 	})
 }
 
+func (s *PostgresStorage) GetSecret(
+	ctx context.Context,
+	login, id string,
+) (domain.Secret, error) {
+	var sec domain.Secret
+	err := s.pool.QueryRow(ctx, secretGetOne, login, id).Scan(
+		&sec.ID,
+		&sec.UserLogin,
+		&sec.Type,
+		&sec.Data,
+		&sec.CreatedAt,
+		&sec.UpdatedAt,
+	)
+	if errors.Is(err, pgx.ErrNoRows) {
+		return domain.Secret{}, domain.ErrSecretNotFound
+	}
+	if err != nil {
+		return domain.Secret{}, HandleDBError(err)
+	}
+
+	return sec, nil
+}
+
 func (s *PostgresStorage) DeleteData(
 	ctx context.Context,
 	login, id string,
@@ -3,6 +3,10 @@ package repository
 const (
 	userCreate = `INSERT INTO users (login, password_hash) VALUES ($1, $2)`
 	userGet    = `SELECT password_hash FROM users WHERE login = $1`
+	userUpdate = `UPDATE users SET password_hash = $2 WHERE login = $1`
+
+	recoveryGet    = `SELECT COALESCE(recovery_hash, '') FROM users WHERE login = $1`
+	recoveryUpdate = `UPDATE users SET recovery_hash = $2 WHERE login = $1`
 
 	secretUpsert = `INSERT INTO secrets (id, user_login, type, data, created_at, updated_at)
 VALUES ($1, $2, $3, $4, $5, $6)
@@ -12,6 +16,7 @@ SET type = EXCLUDED.type,
     updated_at = EXCLUDED.updated_at
 WHERE secrets.updated_at < EXCLUDED.updated_at`
 
-	secretGet    = `SELECT id, user_login, type, data, created_at, updated_at FROM secrets WHERE user_login = $1`
+	secretGet    = `SELECT id, user_login, type, data, created_at, updated_at FROM secrets WHERE user_login = $1 AND ($2::text = '' OR type <> $2)`
+	secretGetOne = `SELECT id, user_login, type, data, created_at, updated_at FROM secrets WHERE user_login = $1 AND id = $2`
 	secretDelete = `DELETE FROM secrets WHERE id = $1 AND user_login = $2`
 )
@@ -78,6 +78,15 @@ func (a *ServerApp) Run() error {
 	mux := http.NewServeMux()
 	mux.HandleFunc("/api/register", a.transport.RegisterHandler)
 	mux.HandleFunc("/api/login", a.transport.LoginHandler)
+	mux.Handle("/api/password", transport.AuthMiddleware(
+		a.cfg.GetSecretKey(),
+		http.HandlerFunc(a.transport.PasswordHandler),
+	))
+	mux.Handle("/api/recovery", transport.AuthMiddleware(
+		a.cfg.GetSecretKey(),
+		http.HandlerFunc(a.transport.RecoveryHandler),
+	))
+	mux.HandleFunc("/api/recover", a.transport.RecoverHandler)
 	mux.Handle("/api/data", transport.AuthMiddleware(
 		a.cfg.GetSecretKey(),
 		http.HandlerFunc(a.transport.DataHandler),
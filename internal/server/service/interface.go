@@ -9,8 +9,13 @@ import (
 type Service interface {
 	Register(ctx context.Context, login, password string) error
 	Login(ctx context.Context, login, password string) (string, error)
+	ChangePassword(ctx context.Context, login, password, newPassword string) error
+	SetRecovery(ctx context.Context, login, recovery string) error
+	ClearRecovery(ctx context.Context, login string) error
+	Recover(ctx context.Context, login, recovery, newPassword string) (string, error)
 
 	SaveSecrets(ctx context.Context, userLogin string, secrets []domain.Secret) error
-	GetData(ctx context.Context, login string) ([]domain.Secret, error)
+	GetData(ctx context.Context, login, skipType string) ([]domain.Secret, error)
+	GetSecret(ctx context.Context, login, id string) (domain.Secret, error)
 	DeleteSecret(ctx context.Context, login, id string) error
 }
@@ -36,10 +36,51 @@ func (s *serviceImplementation) Login(ctx context.Context, login, password strin
 	if hash != s.hashPass(password) {
 		return "", domain.ErrInvalidCreds
 	}
+	return s.issueToken(login)
+}
+
+func (s *serviceImplementation) issueToken(login string) (string, error) {
 	claims := jwt.MapClaims{"login": login, "exp": time.Now().Add(24 * time.Hour).Unix()}
 	return jwt.NewWithClaims(jwt.SigningMethodHS256, claims).SignedString([]byte(s.secretKey))
 }
 
+func (s *serviceImplementation) ChangePassword(ctx context.Context, login, password, newPassword string) error {
+	hash, err := s.repo.GetUser(ctx, login)
+	if err != nil {
+		return domain.ErrInvalidCreds
+	}
+	if hash != s.hashPass(password) {
+		return domain.ErrInvalidCreds
+	}
+	return s.repo.UpdateUser(ctx, login, s.hashPass(newPassword))
+}
+
+func (s *serviceImplementation) SetRecovery(ctx context.Context, login, recovery string) error {
+	return s.repo.UpdateRecovery(ctx, login, s.hashPass(recovery))
+}
+
+// ClearRecovery revokes the registered recovery key; Recover refuses every key
+// until SetRecovery registers a new one.
+func (s *serviceImplementation) ClearRecovery(ctx context.Context, login string) error {
+	return s.repo.UpdateRecovery(ctx, login, "")
+}
+
+// Recover replaces the login password of an account whose owner proves
+// possession of the recovery key registered with SetRecovery.
+func (s *serviceImplementation) Recover(ctx context.Context, login, recovery, newPassword string) (string, error) {
+	hash, err := s.repo.GetRecovery(ctx, login)
+	if err != nil || hash == "" {
+		return "", domain.ErrInvalidCreds
+	}
+	if hash != s.hashPass(recovery) {
+		return "", domain.ErrInvalidCreds
+	}
+	if err := s.repo.UpdateUser(ctx, login, s.hashPass(newPassword)); err != nil {
+		return "", err
+	}
+	return s.issueToken(login)
+}
+
 func (s *serviceImplementation) hashPass(p string) string {
 	h := sha256.New()
 	h.Write([]byte(p))
@@ -62,8 +103,14 @@ func (s *serviceImplementation) SaveSecrets(ctx context.Context, userLogin strin
 	return s.repo.SaveSecrets(ctx, userLogin, secrets)
 }
 
-func (s *serviceImplementation) GetData(ctx context.Context, login string) ([]domain.Secret, error) {
-	return s.repo.GetData(ctx, login)
+// GetData returns every secret of the user except those of skipType, so bulky
+// records such as file chunks can be left out of a listing.
+func (s *serviceImplementation) GetData(ctx context.Context, login, skipType string) ([]domain.Secret, error) {
+	return s.repo.GetData(ctx, login, skipType)
+}
+
+func (s *serviceImplementation) GetSecret(ctx context.Context, login, id string) (domain.Secret, error) {
+	return s.repo.GetSecret(ctx, login, id)
 }
 
 func (s *serviceImplementation) DeleteSecret(ctx context.Context, login, id string) error {
@@ -0,0 +1,117 @@
+package service
+
+import (
+	"context"
+	"errors"
+	"testing"
+
+	"github.com/Doctor46-create/gophkeeper/internal/domain"
+	"github.com/Doctor46-create/gophkeeper/internal/repository"
+)
+
+func newTestService(t *testing.T) Service {
+	t.Helper()
+
+	svc := New(repository.NewMemoryStorage(), "test-secret")
+	if err := svc.Register(context.Background(), "alice", "old-pass"); err != nil {
+		t.Fatalf("Register: %v", err)
+	}
+	return svc
+}
+
+func TestChangePassword(t *testing.T) {
+	ctx := context.Background()
+	svc := newTestService(t)
+
+	if err := svc.ChangePassword(ctx, "alice", "wrong", "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("ChangePassword with a wrong password: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+	if err := svc.ChangePassword(ctx, "bob", "old-pass", "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("ChangePassword for an unknown user: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+
+	if err := svc.ChangePassword(ctx, "alice", "old-pass", "new-pass"); err != nil {
+		t.Fatalf("ChangePassword: %v", err)
+	}
+	if _, err := svc.Login(ctx, "alice", "old-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("Login with the old password: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+	if _, err := svc.Login(ctx, "alice", "new-pass"); err != nil {
+		t.Fatalf("Login with the new password: %v", err)
+	}
+}
+
+func TestRecoverRefusesWithoutRecoveryKey(t *testing.T) {
+	ctx := context.Background()
+	svc := newTestService(t)
+
+	// A user who never registered a key has an empty recovery hash, which must
+	// not match anything, not even an empty key.
+	for _, recovery := range []string{"", "some-key"} {
+		if _, err := svc.Recover(ctx, "alice", recovery, "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+			t.Fatalf("Recover(%q) without a key: got %v, want %v", recovery, err, domain.ErrInvalidCreds)
+		}
+	}
+	if _, err := svc.Login(ctx, "alice", "old-pass"); err != nil {
+		t.Fatalf("Login after refused recovery: %v", err)
+	}
+}
+
+func TestSetRecoveryAndRecover(t *testing.T) {
+	ctx := context.Background()
+	svc := newTestService(t)
+
+	if err := svc.SetRecovery(ctx, "alice", "first-key"); err != nil {
+		t.Fatalf("SetRecovery: %v", err)
+	}
+	if err := svc.SetRecovery(ctx, "bob", "first-key"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("SetRecovery for an unknown user: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+
+	if _, err := svc.Recover(ctx, "alice", "wrong-key", "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("Recover with a wrong key: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+
+	// Registering again replaces the earlier key.
+	if err := svc.SetRecovery(ctx, "alice", "second-key"); err != nil {
+		t.Fatalf("SetRecovery: %v", err)
+	}
+	if _, err := svc.Recover(ctx, "alice", "first-key", "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("Recover with a replaced key: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+
+	token, err := svc.Recover(ctx, "alice", "second-key", "new-pass")
+	if err != nil {
+		t.Fatalf("Recover: %v", err)
+	}
+	if token == "" {
+		t.Fatal("Recover returned an empty token")
+	}
+	if _, err := svc.Login(ctx, "alice", "old-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("Login with the old password: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+	if _, err := svc.Login(ctx, "alice", "new-pass"); err != nil {
+		t.Fatalf("Login with the recovered password: %v", err)
+	}
+}
+
+func TestClearRecovery(t *testing.T) {
+	ctx := context.Background()
+	svc := newTestService(t)
+
+	if err := svc.SetRecovery(ctx, "alice", "key"); err != nil {
+		t.Fatalf("SetRecovery: %v", err)
+	}
+	if err := svc.ClearRecovery(ctx, "alice"); err != nil {
+		t.Fatalf("ClearRecovery: %v", err)
+	}
+	if _, err := svc.Recover(ctx, "alice", "key", "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("Recover with a revoked key: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+	if _, err := svc.Recover(ctx, "alice", "", "new-pass"); !errors.Is(err, domain.ErrInvalidCreds) {
+		t.Fatalf("Recover with an empty key after revoking: got %v, want %v", err, domain.ErrInvalidCreds)
+	}
+	if _, err := svc.Login(ctx, "alice", "old-pass"); err != nil {
+		t.Fatalf("Login after revoking: %v", err)
+	}
+}
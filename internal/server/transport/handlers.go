@@ -4,6 +4,7 @@ import (
 	"bytes"
 	"context"
 	"encoding/json"
+	"errors"
 	"io"
 	"net/http"
 	"time"
@@ -80,6 +81,103 @@ func (h *HTTPTransport) LoginHandler(w http.ResponseWriter, r *http.Request) {
 	json.NewEncoder(w).Encode(domain.Token{JWT: tok})
 }
 
+func (h *HTTPTransport) PasswordHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		http.Error(w, "method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	login, ok := r.Context().Value(UserLoginKey).(string)
+	if !ok {
+		http.Error(w, "Unauthorized", http.StatusUnauthorized)
+		return
+	}
+
+	reqID, _ := r.Context().Value(ReqIDKey).(string)
+	reqLogger := logger.Log.With("request_id", reqID, "login", login)
+
+	var req domain.ChangePasswordRequest
+	if err := json.NewDecoder(r.Body).Decode(&req); err != nil || req.NewPassword == "" {
+		http.Error(w, "bad request", http.StatusBadRequest)
+		return
+	}
+
+	if err := h.svc.ChangePassword(r.Context(), login, req.Password, req.NewPassword); err != nil {
+		reqLogger.Warnw("Password change fail", "error", err)
+		// 401 is left to the auth middleware, which clients take as an expired
+		// session.
+		http.Error(w, err.Error(), http.StatusForbidden)
+		return
+	}
+
+	w.WriteHeader(http.StatusOK)
+}
+
+func (h *HTTPTransport) RecoveryHandler(w http.ResponseWriter, r *http.Request) {
+	login, ok := r.Context().Value(UserLoginKey).(string)
+	if !ok {
+		http.Error(w, "Unauthorized", http.StatusUnauthorized)
+		return
+	}
+
+	reqID, _ := r.Context().Value(ReqIDKey).(string)
+	reqLogger := logger.Log.With("request_id", reqID, "login", login)
+
+	switch r.Method {
+	case http.MethodPost:
+		var req domain.RecoveryRequest
+		if err := json.NewDecoder(r.Body).Decode(&req); err != nil || req.Recovery == "" {
+			http.Error(w, "bad request", http.StatusBadRequest)
+			return
+		}
+
+		if err := h.svc.SetRecovery(r.Context(), login, req.Recovery); err != nil {
+			reqLogger.Warnw("Recovery setup fail", "error", err)
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+			return
+		}
+
+	case http.MethodDelete:
+		if err := h.svc.ClearRecovery(r.Context(), login); err != nil {
+			reqLogger.Warnw("Recovery clear fail", "error", err)
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+			return
+		}
+
+	default:
+		http.Error(w, "method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	w.WriteHeader(http.StatusOK)
+}
+
+func (h *HTTPTransport) RecoverHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		http.Error(w, "method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	reqID, _ := r.Context().Value(ReqIDKey).(string)
+	reqLogger := logger.Log.With("request_id", reqID)
+
+	var req domain.RecoverRequest
+	if err := json.NewDecoder(r.Body).Decode(&req); err != nil || req.NewPassword == "" {
+		http.Error(w, "bad request", http.StatusBadRequest)
+		return
+	}
+
+	tok, err := h.svc.Recover(r.Context(), req.Login, req.Recovery, req.NewPassword)
+	if err != nil {
+		reqLogger.Warnw("Recover fail", "error", err, "login", req.Login)
+		http.Error(w, err.Error(), http.StatusUnauthorized)
+		return
+	}
+
+	w.Header().Set("Content-Type", "application/json")
+	json.NewEncoder(w).Encode(domain.Token{JWT: tok})
+}
+
 func (h *HTTPTransport) DataHandler(w http.ResponseWriter, r *http.Request) {
 	logger.Log.Infow("DataHandler started",
 		"method", r.Method,
@@ -126,7 +224,24 @@ func (h *HTTPTransport) DataHandler(w http.ResponseWriter, r *http.Request) {
 		w.WriteHeader(http.StatusOK)
 
 	case "GET":
-		sec, err := h.svc.GetData(r.Context(), login)
+		if id := r.URL.Query().Get("id"); id != "" {
+			sec, err := h.svc.GetSecret(r.Context(), login, id)
+			if errors.Is(err, domain.ErrSecretNotFound) {
+				http.Error(w, err.Error(), http.StatusNotFound)
+				return
+			}
+			if err != nil {
+				reqLogger.Errorw("Get secret fail", "error", err)
+				http.Error(w, err.Error(), http.StatusInternalServerError)
+				return
+			}
+
+			w.Header().Set("Content-Type", "application/json")
+			json.NewEncoder(w).Encode(sec)
+			return
+		}
+
+		sec, err := h.svc.GetData(r.Context(), login, r.URL.Query().Get("skip_type"))
 		if err != nil {
 			reqLogger.Errorw("Get data fail", "error", err)
 			http.Error(w, err.Error(), http.StatusInternalServerError)
@@ -0,0 +1,76 @@
+package transport
+
+import (
+	"context"
+	"net/http"
+	"net/http/httptest"
+	"strings"
+	"testing"
+
+	"go.uber.org/zap"
+
+	"github.com/Doctor46-create/gophkeeper/internal/logger"
+	"github.com/Doctor46-create/gophkeeper/internal/repository"
+	"github.com/Doctor46-create/gophkeeper/internal/server/service"
+)
+
+func newTestTransport(t *testing.T) (*HTTPTransport, service.Service) {
+	t.Helper()
+
+	logger.Log = zap.NewNop().Sugar()
+	svc := service.New(repository.NewMemoryStorage(), "test-secret")
+	if err := svc.Register(context.Background(), "alice", "pass"); err != nil {
+		t.Fatalf("Register: %v", err)
+	}
+	return &HTTPTransport{svc: svc}, svc
+}
+
+func recoveryRequest(method, body string) *http.Request {
+	r := httptest.NewRequest(method, "/api/recovery", strings.NewReader(body))
+	return r.WithContext(context.WithValue(r.Context(), UserLoginKey, "alice"))
+}
+
+func TestRecoveryHandler(t *testing.T) {
+	h, svc := newTestTransport(t)
+	ctx := context.Background()
+
+	w := httptest.NewRecorder()
+	h.RecoveryHandler(w, recoveryRequest(http.MethodPost, `{"recovery":"key"}`))
+	if w.Code != http.StatusOK {
+		t.Fatalf("POST: got status %d", w.Code)
+	}
+	if _, err := svc.Recover(ctx, "alice", "key", "new-pass"); err != nil {
+		t.Fatalf("Recover after POST: %v", err)
+	}
+
+	w = httptest.NewRecorder()
+	h.RecoveryHandler(w, recoveryRequest(http.MethodDelete, ""))
+	if w.Code != http.StatusOK {
+		t.Fatalf("DELETE: got status %d", w.Code)
+	}
+	if _, err := svc.Recover(ctx, "alice", "key", "other-pass"); err == nil {
+		t.Fatal("Recover succeeded after DELETE")
+	}
+
+	for _, tc := range []struct {
+		method string
+		body   string
+		want   int
+	}{
+		{http.MethodPost, `{"recovery":""}`, http.StatusBadRequest},
+		{http.MethodPost, `not json`, http.StatusBadRequest},
+		{http.MethodGet, "", http.StatusMethodNotAllowed},
+	} {
+		w = httptest.NewRecorder()
+		h.RecoveryHandler(w, recoveryRequest(tc.method, tc.body))
+		if w.Code != tc.want {
+			t.Errorf("%s %q: got status %d, want %d", tc.method, tc.body, w.Code, tc.want)
+		}
+	}
+
+	w = httptest.NewRecorder()
+	h.RecoveryHandler(w, httptest.NewRequest(http.MethodDelete, "/api/recovery", nil))
+	if w.Code != http.StatusUnauthorized {
+		t.Fatalf("DELETE without a login: got status %d", w.Code)
+	}
+}
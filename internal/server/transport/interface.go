@@ -5,5 +5,8 @@ import "net/http"
 type Transport interface {
 	RegisterHandler(w http.ResponseWriter, r *http.Request)
 	LoginHandler(w http.ResponseWriter, r *http.Request)
+	PasswordHandler(w http.ResponseWriter, r *http.Request)
+	RecoveryHandler(w http.ResponseWriter, r *http.Request)
+	RecoverHandler(w http.ResponseWriter, r *http.Request)
 	DataHandler(w http.ResponseWriter, r *http.Request)
 }